@@ -0,0 +1,226 @@
+//! Commands that can be run from the command line instead of starting the server and crawler.
+//! `ceridwen <command> [arguments]`
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use bzip2::Compression;
+use log::info;
+
+use crate::config::Config;
+use crate::config::Ingester;
+use crate::error::Error;
+use crate::index_sled::collection_names;
+use crate::index_sled::Index;
+use crate::index_sled::DEFAULT_COLLECTION;
+use crate::preferences::Preferences;
+use crate::preferences::DEFAULT_BOOST;
+
+const USAGE: &str = "Usage: ceridwen [--collection <name>] [command]
+
+With no command the server and crawler are started.
+
+Options:
+    --collection <name>         Collection the command works on. Defaults to the default collection
+
+Commands:
+    delete-page <url>           Remove a single page from the index
+    delete-host <host>          Remove every page on a host from the index
+    delete-ingester <name>      Remove every page found by an ingester from the index
+    export-index <file> [--no-postings]
+                                Write the index to a file. Files ending in .bz2 are compressed
+    import-index <file>         Read an exported index into this one
+    reindex                     Rebuild the word index from the stored text of each page
+    rank                        Work out how well linked to each page is from the links between them
+    list-collections            Show the names of every collection
+    delete-collection <name>    Remove a collection and everything in it
+    boost-domain <domain> [factor]
+                                Multiply the scores of pages on a domain by factor. Defaults to 2
+    unboost-domain <domain>     Stop boosting a domain
+    block-domain <domain>       Never show pages on a domain in results
+    unblock-domain <domain>     Show pages on a blocked domain again
+    pin <query> <url>           Always show the page at url first when searching for query
+    unpin <query> <url>         Stop pinning a page to a query
+    list-preferences            Show the boosted and blocked domains and pinned pages
+    help                        Show this message";
+
+/// Run the command described by args. args should not include the program name.
+pub async fn run_command(config: &Config, args: &[String]) -> Result<(), Error> {
+    let (collection, args) = match args {
+        [option, name, rest @ ..] if option == "--collection" => (name.as_str(), rest),
+        _ => (DEFAULT_COLLECTION, args),
+    };
+    if args.is_empty() {
+        println!("{USAGE}");
+        return Err(Error::InvalidCommand("missing command".to_string()));
+    }
+
+    // these work on collections as a whole so must not open one
+    match (args[0].as_str(), &args[1..]) {
+        ("list-collections", []) => {
+            for name in collection_names()? {
+                println!("{name}");
+            }
+            return Ok(());
+        }
+        ("delete-collection", [name]) => {
+            Index::delete_collection(name).await?;
+            info!("Deleted collection {name}");
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    if let Some(result) = preferences_command(args) {
+        return result;
+    }
+
+    let index = Index::open(collection).await?;
+
+    let command = args[0].as_str();
+    let result = match (command, &args[1..]) {
+        ("delete-page", [url]) => {
+            if index.delete_page(url)? {
+                info!("Deleted {url} from the index");
+            } else {
+                info!("{url} was not in the index");
+            }
+            Ok(())
+        }
+        ("delete-host", [host]) => {
+            let count = index.delete_by_host(host)?;
+            info!("Deleted {count} pages for host {host}");
+            Ok(())
+        }
+        ("delete-ingester", [name]) => {
+            let legacy_host = config.ingester(name).and_then(Ingester::host);
+            let count = index.delete_by_ingester(name, legacy_host.as_deref())?;
+            info!("Deleted {count} pages for ingester {name}");
+            Ok(())
+        }
+        ("export-index", [path]) => export_index(&index, path, true),
+        ("export-index", [path, flag]) if flag == "--no-postings" => {
+            export_index(&index, path, false)
+        }
+        ("import-index", [path]) => import_index(&index, path),
+        ("reindex", []) => {
+            let count = index.reindex()?;
+            info!("Reindexed {count} pages");
+            Ok(())
+        }
+        ("rank", []) => {
+            let count = index.update_ranks(true)?;
+            info!("Ranked {count} pages");
+            Ok(())
+        }
+        ("help", _) | ("--help", _) | ("-h", _) => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => {
+            println!("{USAGE}");
+            Err(Error::InvalidCommand(args.join(" ")))
+        }
+    };
+
+    index.flush()?;
+    result
+}
+
+/// Run a command that changes the search preferences, None if args isn't one. Preferences aren't part of any
+/// collection so these don't open an index. A running server only sees the changes once it is restarted.
+fn preferences_command(args: &[String]) -> Option<Result<(), Error>> {
+    let command = args[0].as_str();
+    let args = &args[1..];
+    if command == "list-preferences" && args.is_empty() {
+        return Some(list_preferences());
+    }
+
+    let mut preferences = match Preferences::load() {
+        Ok(p) => p,
+        Err(e) => return Some(Err(e)),
+    };
+    let result = match (command, args) {
+        ("boost-domain", [domain]) => preferences.boost(domain, DEFAULT_BOOST),
+        ("boost-domain", [domain, factor]) => factor
+            .parse()
+            .map_err(|_| Error::InvalidPreference(format!("{factor} is not a number")))
+            .and_then(|factor| preferences.boost(domain, factor)),
+        ("unboost-domain", [domain]) => {
+            if !preferences.remove_boost(domain) {
+                info!("{domain} was not boosted");
+            }
+            Ok(())
+        }
+        ("block-domain", [domain]) => {
+            preferences.block(domain);
+            Ok(())
+        }
+        ("unblock-domain", [domain]) => {
+            if !preferences.unblock(domain) {
+                info!("{domain} was not blocked");
+            }
+            Ok(())
+        }
+        ("pin", [query, url]) => preferences.pin(query, url),
+        ("unpin", [query, url]) => {
+            if !preferences.unpin(query, url) {
+                info!("{url} was not pinned for {query}");
+            }
+            Ok(())
+        }
+        _ => return None,
+    };
+
+    Some(result.and_then(|_| preferences.save()))
+}
+
+fn list_preferences() -> Result<(), Error> {
+    let preferences = Preferences::load()?;
+    for (domain, factor) in preferences.boosted.iter() {
+        println!("boost {domain} {factor}");
+    }
+    for domain in preferences.blocked.iter() {
+        println!("block {domain}");
+    }
+    for (query, urls) in preferences.pinned.iter() {
+        for url in urls.iter() {
+            println!("pin \"{query}\" {url}");
+        }
+    }
+    Ok(())
+}
+
+fn is_compressed(path: &str) -> bool {
+    path.ends_with(".bz2")
+}
+
+fn export_index(index: &Index, path: &str, include_postings: bool) -> Result<(), Error> {
+    info!("Exporting index to {path}");
+    let file = File::create(path)?;
+    let count = if is_compressed(path) {
+        let mut writer = BufWriter::new(BzEncoder::new(file, Compression::default()));
+        let count = index.export(&mut writer, include_postings)?;
+        writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+        count
+    } else {
+        index.export(&mut BufWriter::new(file), include_postings)?
+    };
+    info!("Exported {count} pages to {path}");
+    Ok(())
+}
+
+fn import_index(index: &Index, path: &str) -> Result<(), Error> {
+    info!("Importing index from {path}");
+    let file = File::open(path)?;
+    let count = if is_compressed(path) {
+        index.import(BufReader::new(MultiBzDecoder::new(file)))?
+    } else {
+        index.import(BufReader::new(file))?
+    };
+    info!("Imported {count} pages from {path}");
+    Ok(())
+}
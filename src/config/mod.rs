@@ -7,6 +7,7 @@ use time;
 use toml;
 
 use crate::error::Error;
+use crate::index_sled::DEFAULT_COLLECTION;
 use crate::utils;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -16,6 +17,9 @@ pub struct Config {
     pub server: Server,
     pub crawler: Crawler,
     pub last_update: time::OffsetDateTime,
+    /// searches published as atom feeds by the server
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub feeds: Vec<SearchFeed>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -26,6 +30,45 @@ pub struct Ingester {
     pub base_url: Option<String>,
     pub last_update: time::OffsetDateTime,
     pub options: HashMap<String, String>,
+    /// name of the collection pages from this ingester are stored in. Uses the default collection if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+}
+
+/// Ingester option for the most text (in bytes) we keep for each page it finds. 0 disables keeping the text.
+pub const MAX_DOCUMENT_SIZE_OPTION: &str = "max_document_size";
+
+/// Default for the most text we keep for each page. Big enough for nearly everything without letting the odd huge
+/// page eat the disk.
+const DEFAULT_MAX_DOCUMENT_SIZE: usize = 1024 * 1024;
+
+/// Feed ingester option to fetch the page each item links to and index that rather than the item's summary.
+pub const DEEP_OPTION: &str = "deep";
+
+impl Ingester {
+    pub fn max_document_size(&self) -> Result<usize, Error> {
+        match self.options.get(MAX_DOCUMENT_SIZE_OPTION) {
+            Some(size) => Ok(size.parse()?),
+            None => Ok(DEFAULT_MAX_DOCUMENT_SIZE),
+        }
+    }
+
+    pub fn deep(&self) -> Result<bool, Error> {
+        match self.options.get(DEEP_OPTION) {
+            Some(deep) => Ok(deep.parse()?),
+            None => Ok(false),
+        }
+    }
+
+    pub fn collection(&self) -> &str {
+        self.collection.as_deref().unwrap_or(DEFAULT_COLLECTION)
+    }
+
+    /// The host of the base url, if there is one
+    pub fn host(&self) -> Option<String> {
+        let base_url = url::Url::parse(self.base_url.as_deref()?).ok()?;
+        base_url.host_str().map(str::to_string)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -35,6 +78,41 @@ pub struct Server {
 
     /// number of server workers needed. The default of 2 should be more than enough for most households.
     pub workers: usize,
+
+    /// number of recent searches to keep the results of, so repeating a search is quick. 0 turns this off.
+    #[serde(default = "default_search_cache_size")]
+    pub search_cache_size: usize,
+
+    /// keep a history of searches made through the server. Turning this off also clears any history already kept.
+    #[serde(default = "default_search_history")]
+    pub search_history: bool,
+
+    /// number of searches to keep in the history
+    #[serde(default = "default_search_history_size")]
+    pub search_history_size: usize,
+}
+
+fn default_search_cache_size() -> usize {
+    1000
+}
+
+fn default_search_history() -> bool {
+    true
+}
+
+fn default_search_history_size() -> usize {
+    1000
+}
+
+/// A search whose most recently indexed results are served as an atom feed at `/feeds/<name>.xml`, so a feed reader
+/// can tell us when something new matches it.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SearchFeed {
+    pub name: String,
+    pub query: String,
+    /// only search this collection. Searches every collection when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -45,6 +123,40 @@ pub struct Crawler {
 
     /// Minimum amount of time before the crawler will go back to a page to check for changes.
     pub min_update_interval: time::Duration,
+
+    /// Shortest time between starting requests to the same host. A longer `Crawl-delay` in the site's robots.txt
+    /// wins over this.
+    #[serde(default = "default_host_delay")]
+    pub host_delay: time::Duration,
+
+    /// Most requests that can be made to the same host at once
+    #[serde(default = "default_host_connections")]
+    pub host_connections: usize,
+
+    /// How many times to retry a request that failed in a way that might work next time, like a dropped connection
+    /// or a server error
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+
+    /// How long to wait before the first retry. Each retry after that waits about twice as long as the one before.
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: time::Duration,
+}
+
+fn default_host_delay() -> time::Duration {
+    time::Duration::seconds(1)
+}
+
+fn default_host_connections() -> usize {
+    2
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_retry_delay() -> time::Duration {
+    time::Duration::seconds(2)
 }
 
 impl Config {
@@ -79,6 +191,11 @@ impl Config {
         Ok(config)
     }
 
+    /// The ingester with the given name
+    pub fn ingester(&self, name: &str) -> Option<&Ingester> {
+        self.targets.iter().find(|t| t.name == name)
+    }
+
     /// Save the this config object to a file in toml format
     pub fn save(&self) -> Result<(), Error> {
         let config_path = Config::config_path();
@@ -101,6 +218,7 @@ impl Default for Config {
                     base_url: Some("https://parsecsreach.org/index.xml".to_string()),
                     last_update: time::OffsetDateTime::now_utc() - time::Duration::days(7),
                     options: HashMap::new(),
+                    collection: None,
                 },
                 // A test ingester for an rss feed that has a robots.txt file
                 // Ingester {
@@ -118,18 +236,28 @@ impl Default for Config {
                     base_url: None,
                     last_update: time::OffsetDateTime::now_utc() - time::Duration::days(90),
                     options: HashMap::new(),
+                    // wikipedia is huge, keep it apart so it can be rebuilt or dropped on its own
+                    collection: Some("wikipedia".to_string()),
                 },
             ],
             log_level: "info".to_string(),
             server: Server {
                 port: 8080,
                 workers: 2,
+                search_cache_size: default_search_cache_size(),
+                search_history: default_search_history(),
+                search_history_size: default_search_history_size(),
             },
             crawler: Crawler {
                 workers: 16,
                 min_update_interval: time::Duration::days(1),
+                host_delay: default_host_delay(),
+                host_connections: default_host_connections(),
+                retries: default_retries(),
+                retry_delay: default_retry_delay(),
             },
             last_update: time::OffsetDateTime::now_utc() - time::Duration::days(90),
+            feeds: Vec::new(),
         }
     }
 }
@@ -0,0 +1,476 @@
+//! Turns html pages into the text we want to index, along with what the page says about itself.
+//!
+//! Only the main content of a page is kept. Anything the page marks as the main content (`<main>`, `<article>` or
+//! `role="main"`) is preferred over the rest. Navigation, headers, footers, forms and anything whose class or id
+//! looks like a sidebar, comment section or advert are dropped, as are blocks of text that are mostly links, which
+//! are usually menus or lists of related pages. Headings and paragraphs are kept one to a line.
+
+use url::Url;
+
+use crate::crawler::robots_tags;
+use crate::crawler::robots_tags::RobotsDirectives;
+
+use tokenizer::decode_entities;
+use tokenizer::Token;
+use tokenizer::Tokenizer;
+
+mod tokenizer;
+
+/// Elements whose contents are never shown to the reader
+const HIDDEN_ELEMENTS: [&str; 7] = [
+    "head", "script", "style", "noscript", "template", "svg", "iframe",
+];
+
+/// Elements that are never the main content of a page
+const BOILERPLATE_ELEMENTS: [&str; 8] = [
+    "nav", "aside", "form", "menu", "button", "select", "dialog", "label",
+];
+
+/// Elements that are boilerplate for the page as a whole, but not inside an article where they hold its title or
+/// byline
+const PAGE_BOILERPLATE_ELEMENTS: [&str; 2] = ["header", "footer"];
+
+/// Roles that mark something as not being the main content
+const BOILERPLATE_ROLES: [&str; 6] = [
+    "navigation",
+    "banner",
+    "contentinfo",
+    "complementary",
+    "search",
+    "dialog",
+];
+
+/// Parts of class names and ids that usually mean something isn't the main content
+const BOILERPLATE_HINTS: [&str; 17] = [
+    "sidebar",
+    "comment",
+    "footer",
+    "menu",
+    "navbar",
+    "breadcrumb",
+    "share",
+    "social",
+    "cookie",
+    "banner",
+    "advert",
+    "promo",
+    "related",
+    "popup",
+    "newsletter",
+    "subscribe",
+    "sponsor",
+];
+
+/// Parts of class names and ids that usually mean something is the main content, even if it also has a boilerplate
+/// hint. `article-footer` is boilerplate, `article-body` isn't.
+const CONTENT_HINTS: [&str; 5] = [
+    "article-body",
+    "post-body",
+    "entry-content",
+    "story",
+    "main-content",
+];
+
+/// Elements that never have an end tag
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements that start a new line of text, so words either side of them don't run together
+const BLOCK_ELEMENTS: [&str; 32] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "tr",
+];
+
+const HEADINGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Blocks where more than this much of the text is in links are treated as navigation
+const MAX_LINK_DENSITY: f64 = 0.5;
+
+/// What we found in an html page
+#[derive(Debug, Default, PartialEq)]
+pub struct Document {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// lower case language tag, `en` or `en-gb`
+    pub language: Option<String>,
+    /// where the page says it really lives, if it says
+    pub canonical: Option<Url>,
+    /// the main content of the page, one heading or paragraph per line
+    pub text: String,
+    /// every http and https link on the page, without fragments. Empty if the page asks for its links not to be
+    /// followed, and links marked `rel="nofollow"` are left out.
+    pub links: Vec<Url>,
+    /// what the robots meta tags on the page allow
+    pub robots: RobotsDirectives,
+}
+
+/// An element we are inside of, and what that means for the text in it
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    hidden: bool,
+    boilerplate: bool,
+    main: bool,
+    heading: bool,
+    link: bool,
+}
+
+/// A line of text, and how much of it was in links
+#[derive(Debug, Default)]
+struct Block {
+    text: String,
+    link_length: usize,
+    main: bool,
+    heading: bool,
+}
+
+impl Block {
+    fn mostly_links(&self) -> bool {
+        self.link_length as f64 > self.text.len() as f64 * MAX_LINK_DENSITY
+    }
+}
+
+fn has_hint(value: &str, hints: &[&str]) -> bool {
+    hints.iter().any(|hint| value.contains(hint))
+}
+
+fn is_boilerplate(name: &str, token: &Token, parent: &Element) -> bool {
+    if BOILERPLATE_ELEMENTS.contains(&name)
+        || (PAGE_BOILERPLATE_ELEMENTS.contains(&name) && !parent.main)
+        || token
+            .attribute("role")
+            .is_some_and(|role| BOILERPLATE_ROLES.contains(&role.trim()))
+    {
+        return true;
+    }
+
+    let names = format!(
+        "{} {}",
+        token.attribute("class").unwrap_or_default(),
+        token.attribute("id").unwrap_or_default()
+    )
+    .to_lowercase();
+    has_hint(&names, &BOILERPLATE_HINTS) && !has_hint(&names, &CONTENT_HINTS)
+}
+
+fn is_hidden(name: &str, token: &Token) -> bool {
+    HIDDEN_ELEMENTS.contains(&name)
+        || token.attribute("hidden").is_some()
+        || token.attribute("aria-hidden") == Some("true")
+        || token
+            .attribute("style")
+            .is_some_and(|style| style.replace(' ', "").contains("display:none"))
+}
+
+fn is_main(name: &str, token: &Token) -> bool {
+    name == "main" || name == "article" || token.attribute("role") == Some("main")
+}
+
+/// Tidy up whitespace, returning None if nothing is left
+fn tidy(text: &str) -> Option<String> {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+/// Pull the main content and details of a page out of its html. base is where the page came from, which relative
+/// links are resolved against.
+pub fn parse(html: &str, base: &Url) -> Document {
+    let mut document = Document::default();
+    let mut title: Option<String> = None;
+    let mut og_title = None;
+    let mut og_description = None;
+
+    let root = Element::default();
+    let mut stack: Vec<Element> = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut block = Block::default();
+
+    for token in Tokenizer::new(html) {
+        match &token {
+            Token::Start {
+                name, self_closing, ..
+            } => {
+                let name = name.as_str();
+                match name {
+                    "html" => {
+                        if let Some(language) = token.attribute("lang").and_then(tidy) {
+                            document.language = Some(language.to_lowercase());
+                        }
+                    }
+                    "title" if title.is_none() => title = Some(String::new()),
+                    "meta" => {
+                        let key = token
+                            .attribute("name")
+                            .or(token.attribute("property"))
+                            .or(token.attribute("http-equiv"))
+                            .unwrap_or_default()
+                            .to_lowercase();
+                        let content = token.attribute("content").map(decode_entities);
+                        match key.as_str() {
+                            "description" => {
+                                document.description = content.as_deref().and_then(tidy)
+                            }
+                            "og:description" => og_description = content.as_deref().and_then(tidy),
+                            "og:title" => og_title = content.as_deref().and_then(tidy),
+                            "robots" => document
+                                .robots
+                                .add_rules(content.as_deref().unwrap_or_default()),
+                            name if robots_tags::is_us(name) => document
+                                .robots
+                                .add_rules(content.as_deref().unwrap_or_default()),
+                            "content-language" if document.language.is_none() => {
+                                document.language =
+                                    content.as_deref().and_then(tidy).map(|l| l.to_lowercase())
+                            }
+                            _ => {}
+                        }
+                    }
+                    "link" => {
+                        let canonical = token.attribute("rel").is_some_and(|rel| {
+                            rel.split_whitespace()
+                                .any(|r| r.eq_ignore_ascii_case("canonical"))
+                        });
+                        if canonical {
+                            document.canonical =
+                                token.attribute("href").and_then(|href| resolve(base, href));
+                        }
+                    }
+                    "a" if !has_rel(&token, "nofollow") => {
+                        if let Some(link) =
+                            token.attribute("href").and_then(|href| resolve(base, href))
+                        {
+                            if !document.links.contains(&link) {
+                                document.links.push(link);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                if BLOCK_ELEMENTS.contains(&name) {
+                    end_block(&mut blocks, &mut block);
+                }
+                if !*self_closing && !VOID_ELEMENTS.contains(&name) {
+                    let parent = stack.last().unwrap_or(&root);
+                    let element = Element {
+                        name: name.to_string(),
+                        hidden: parent.hidden || is_hidden(name, &token),
+                        boilerplate: parent.boilerplate || is_boilerplate(name, &token, parent),
+                        main: parent.main || is_main(name, &token),
+                        heading: parent.heading || HEADINGS.contains(&name),
+                        link: parent.link || name == "a",
+                    };
+                    stack.push(element);
+                }
+            }
+            Token::End { name } => {
+                if let Some(position) = stack.iter().rposition(|e| e.name == *name) {
+                    stack.truncate(position);
+                }
+                if BLOCK_ELEMENTS.contains(&name.as_str()) {
+                    end_block(&mut blocks, &mut block);
+                }
+            }
+            Token::Text(text) => {
+                let element = stack.last().unwrap_or(&root);
+                // svg images have titles of their own
+                if element.name == "title" && !stack.iter().any(|e| e.name == "svg") {
+                    if let Some(title) = title.as_mut() {
+                        title.push_str(text);
+                    }
+                }
+                if element.hidden || element.boilerplate {
+                    continue;
+                }
+
+                let text = decode_entities(text);
+                if element.link {
+                    block.link_length += text.trim().len();
+                }
+                block.main |= element.main;
+                block.heading |= element.heading;
+                block.text.push_str(&text);
+            }
+        }
+    }
+    end_block(&mut blocks, &mut block);
+
+    let has_main = blocks.iter().any(|b| b.main);
+    document.text = blocks
+        .iter()
+        .filter(|b| b.main || !has_main)
+        .filter(|b| b.heading || !b.mostly_links())
+        .map(|b| b.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    document.title = title
+        .map(|t| decode_entities(&t))
+        .as_deref()
+        .and_then(tidy)
+        .or(og_title);
+    document.description = document.description.or(og_description);
+    if document.robots.nofollow {
+        document.links.clear();
+    }
+    document
+}
+
+fn has_rel(token: &Token, rel: &str) -> bool {
+    token
+        .attribute("rel")
+        .is_some_and(|rels| rels.split_whitespace().any(|r| r.eq_ignore_ascii_case(rel)))
+}
+
+/// Add the block to blocks with its whitespace tidied up, if there is anything in it.
+fn end_block(blocks: &mut Vec<Block>, block: &mut Block) {
+    let finished = std::mem::take(block);
+    if let Some(text) = tidy(&finished.text) {
+        blocks.push(Block { text, ..finished });
+    }
+}
+
+/// Turn a link on the page into a full url. Only http and https links are kept.
+fn resolve(base: &Url, href: &str) -> Option<Url> {
+    let mut url = base.join(decode_entities(href.trim()).as_str()).ok()?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return None;
+    }
+    url.set_fragment(None);
+    Some(url)
+}
+
+/// The readable text of a fragment of html, such as the content of a feed item.
+pub fn extract_text(html: &str, base: &Url) -> String {
+    parse(html, base).text
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use crate::crawler::html::extract_text;
+    use crate::crawler::html::parse;
+
+    fn base() -> Url {
+        Url::parse("https://example.com/animals/otters.html").unwrap()
+    }
+
+    #[test]
+    fn test_metadata() {
+        let html = r#"<!DOCTYPE html><html lang="en-GB"><head>
+            <title>Otters &amp; more</title>
+            <meta name="description" content="All about otters">
+            <meta property="og:title" content="Otters!">
+            <link rel="canonical" href="/otters">
+            </head><body><p>Otters are <a href="sea.html#top">sea</a> mammals.
+            <a href="mailto:otters@example.com">mail</a></p></body></html>"#;
+        let document = parse(html, &base());
+
+        assert_eq!(document.title.as_deref(), Some("Otters & more"));
+        assert_eq!(document.description.as_deref(), Some("All about otters"));
+        assert_eq!(document.language.as_deref(), Some("en-gb"));
+        assert_eq!(
+            document.canonical.unwrap().as_str(),
+            "https://example.com/otters"
+        );
+        assert_eq!(
+            document.links,
+            [Url::parse("https://example.com/animals/sea.html").unwrap()]
+        );
+        assert_eq!(document.text, "Otters are sea mammals. mail");
+    }
+
+    #[test]
+    fn test_main_content() {
+        let html = r#"<html><head><style>p { color: red }</style></head><body>
+            <header><a href="/">Home</a> <a href="/about">About</a></header>
+            <nav><ul><li><a href="/a">Animals</a></li></ul></nav>
+            <article>
+                <header><h1>Otters</h1><p class="byline">By someone</p></header>
+                <p>Otters are <b>semi</b>aquatic
+                mammals.</p>
+                <script>track()</script>
+                <div class="share-buttons">Share this</div>
+                <p hidden>secret</p>
+                <ul><li>Sea</li><li>River</li></ul>
+                <div class="article-body comments-enabled">They eat fish.</div>
+            </article>
+            <div id="comments"><p>Great article!</p></div>
+            <footer>Copyright</footer>
+            </body></html>"#;
+        assert_eq!(
+            extract_text(html, &base()),
+            "Otters\nBy someone\nOtters are semiaquatic mammals.\nSea\nRiver\nThey eat fish."
+        );
+    }
+
+    #[test]
+    fn test_link_lists_are_dropped() {
+        let html = r#"<body><div><p>Otters hold hands while they sleep.</p>
+            <p><a href="/1">Related one</a> | <a href="/2">Related two</a></p>
+            <p>Read the <a href="/more">rest</a> of the article to find out why.</p></div></body>"#;
+        assert_eq!(
+            extract_text(html, &base()),
+            "Otters hold hands while they sleep.\nRead the rest of the article to find out why."
+        );
+    }
+
+    #[test]
+    fn test_robots_meta_tags() {
+        let html = r#"<head><meta name="robots" content="nofollow"><meta name="otherbot" content="noindex"></head>
+            <body><a href="/a">a</a></body>"#;
+        let document = parse(html, &base());
+        assert!(document.robots.nofollow && !document.robots.noindex);
+        assert!(document.links.is_empty());
+
+        let html = r#"<head><meta name="Ceridwen" content="noindex"></head>
+            <body><a href="/a">a</a> <a rel="ugc nofollow" href="/b">b</a></body>"#;
+        let document = parse(html, &base());
+        assert!(document.robots.noindex && !document.robots.nofollow);
+        assert_eq!(
+            document.links,
+            [Url::parse("https://example.com/a").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_plain_text() {
+        // feed summaries are often just text, which should come through as it is
+        assert_eq!(
+            extract_text("Otters &amp; beavers", &base()),
+            "Otters & beavers"
+        );
+    }
+}
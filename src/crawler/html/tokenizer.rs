@@ -0,0 +1,278 @@
+//! Splits html up into tags and text.
+//!
+//! This is a forgiving tokenizer rather than a full html parser. Real pages are often broken, so unclosed tags,
+//! unquoted attributes and stray `<` characters are all passed over rather than treated as errors.
+
+#[derive(Debug, PartialEq)]
+pub enum Token<'a> {
+    Start {
+        /// lower case tag name
+        name: String,
+        /// lower case attribute names, with their values
+        attributes: Vec<(String, String)>,
+        /// written as `<tag/>`, so there won't be an end tag
+        self_closing: bool,
+    },
+    End {
+        name: String,
+    },
+    /// Text between tags. Entities have not been decoded.
+    Text(&'a str),
+}
+
+impl Token<'_> {
+    /// The value of an attribute of a start tag. Attributes without a value give an empty string.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        match self {
+            Token::Start { attributes, .. } => attributes
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// Splits html up into tags and text. Comments, doctypes and processing instructions are skipped.
+pub struct Tokenizer<'a> {
+    html: &'a str,
+    position: usize,
+    /// set inside script and style elements, whose contents are text until their end tag whatever they look like
+    raw_text_until: Option<String>,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(html: &'a str) -> Self {
+        Tokenizer {
+            html,
+            position: 0,
+            raw_text_until: None,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.html[self.position..]
+    }
+
+    /// Move past the next occurrence of end, or to the end of the html if there isn't one.
+    fn skip_past(&mut self, end: &str) {
+        match self.rest().find(end) {
+            Some(i) => self.position += i + end.len(),
+            None => self.position = self.html.len(),
+        }
+    }
+
+    fn raw_text(&mut self, name: String) -> Token<'a> {
+        let rest = self.rest();
+        let close = format!("</{name}");
+        let end = find_ignore_case(rest, &close).unwrap_or(rest.len());
+        self.position += end;
+        Token::Text(&rest[..end])
+    }
+
+    fn tag(&mut self) -> Option<Token<'a>> {
+        let rest = self.rest();
+        let (closing, body) = match rest[1..].strip_prefix('/') {
+            Some(body) => (true, body),
+            None => (false, &rest[1..]),
+        };
+        let name_end = body
+            .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .unwrap_or(body.len());
+        let name = body[..name_end].to_ascii_lowercase();
+
+        let mut attributes = Vec::new();
+        let mut input = &body[name_end..];
+        loop {
+            input = input.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+            if input.is_empty() || input.starts_with('>') {
+                break;
+            }
+            let attribute_end = input
+                .find(|c: char| c.is_whitespace() || c == '=' || c == '>')
+                .unwrap_or(input.len())
+                .max(1);
+            let attribute = input[..attribute_end].to_ascii_lowercase();
+            input = input[attribute_end..].trim_start();
+
+            let mut value = "";
+            if let Some(after) = input.strip_prefix('=') {
+                let after = after.trim_start();
+                (value, input) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => match after[1..].find(quote) {
+                        Some(i) => (&after[1..i + 1], &after[i + 2..]),
+                        None => (&after[1..], ""),
+                    },
+                    _ => {
+                        let end = after
+                            .find(|c: char| c.is_whitespace() || c == '>')
+                            .unwrap_or(after.len());
+                        (&after[..end], &after[end..])
+                    }
+                };
+            }
+            attributes.push((attribute, value.to_string()));
+        }
+        let consumed = rest.len() - input.len();
+        let self_closing = rest[..consumed].ends_with('/');
+        self.position += consumed + usize::from(input.starts_with('>'));
+
+        if closing {
+            return Some(Token::End { name });
+        }
+        if !self_closing && (name == "script" || name == "style") {
+            self.raw_text_until = Some(name.clone());
+        }
+        Some(Token::Start {
+            name,
+            attributes,
+            self_closing,
+        })
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(name) = self.raw_text_until.take() {
+                let token = self.raw_text(name);
+                if token != Token::Text("") {
+                    return Some(token);
+                }
+            }
+
+            let rest = self.rest();
+            if rest.is_empty() {
+                return None;
+            }
+
+            if !rest.starts_with('<') {
+                let end = rest.find('<').unwrap_or(rest.len());
+                self.position += end;
+                return Some(Token::Text(&rest[..end]));
+            }
+
+            if rest.starts_with("<!--") {
+                self.skip_past("-->");
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                self.skip_past(">");
+            } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/') {
+                return self.tag();
+            } else {
+                // a < that doesn't start a tag is just text
+                self.position += 1;
+                return Some(Token::Text(&rest[..1]));
+            }
+        }
+    }
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Replace character references like `&amp;` and `&#39;` with the characters they stand for. Only the common named
+/// ones are understood, anything else is left as it is.
+pub fn decode_entities(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| {
+                let entity = &rest[1..end + 1];
+                let character = match entity {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    "nbsp" => Some(' '),
+                    _ => entity
+                        .strip_prefix("#x")
+                        .or(entity.strip_prefix("#X"))
+                        .map(|hex| u32::from_str_radix(hex, 16))
+                        .or(entity.strip_prefix('#').map(|dec| dec.parse()))
+                        .and_then(Result::ok)
+                        .and_then(char::from_u32),
+                };
+                character.map(|c| (c, end + 2))
+            });
+        match decoded {
+            Some((c, length)) => {
+                result.push(c);
+                rest = &rest[length..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::html::tokenizer::decode_entities;
+    use crate::crawler::html::tokenizer::Token;
+    use crate::crawler::html::tokenizer::Tokenizer;
+
+    #[test]
+    fn test_tokenizer() {
+        let tokens: Vec<Token> =
+            Tokenizer::new("<!DOCTYPE html><A HREF=/x class='a b' hidden>x < y</a><!-- c --><br/>")
+                .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Start {
+                    name: "a".to_string(),
+                    attributes: vec![
+                        ("href".to_string(), "/x".to_string()),
+                        ("class".to_string(), "a b".to_string()),
+                        ("hidden".to_string(), "".to_string()),
+                    ],
+                    self_closing: false,
+                },
+                Token::Text("x "),
+                Token::Text("<"),
+                Token::Text(" y"),
+                Token::End {
+                    name: "a".to_string()
+                },
+                Token::Start {
+                    name: "br".to_string(),
+                    attributes: vec![],
+                    self_closing: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_script_is_raw_text() {
+        let tokens: Vec<Token> = Tokenizer::new("<script>if (a<b) { x = '</p>' }</SCRIPT>after")
+            .skip(1)
+            .collect();
+        assert_eq!(tokens[0], Token::Text("if (a<b) { x = '</p>' }"));
+        assert_eq!(tokens[2], Token::Text("after"));
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(
+            decode_entities("fish &amp; chips &#39;n&#x27; &lt;peas&gt; & &bogus; &nbsp;"),
+            "fish & chips 'n' <peas> & &bogus;  "
+        );
+    }
+}
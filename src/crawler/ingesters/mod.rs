@@ -1,26 +1,31 @@
 use crate::config::Config;
 use crate::config::Ingester;
+use crate::crawler::html;
+use crate::crawler::html::Document;
+use crate::crawler::robots_text;
+use crate::crawler::validators;
+use crate::crawler::web_client;
 use crate::error::Error;
 use crate::index_sled::Index;
 use log::info;
 use log::warn;
+use reqwest::header::HeaderMap;
+use reqwest::Client;
+use std::time::Instant;
+use url::Url;
 
 mod rss_ingester;
+mod sitemap_ingester;
 mod wikipedia;
 
-/// These are tools for reading in a data source and adding to the index so we can search things.
-///
+// These are tools for reading in a data source and adding to the index so we can search things.
 
 /// entry point and error logging wrapper
 pub async fn process_ingester(ingester_config: Ingester, config: Config, index: Index) {
     let name = ingester_config.name.clone();
     let result = process(ingester_config, config, index).await;
-    if result.is_err() {
-        warn!(
-            "Error processing ingester {}: {}",
-            name,
-            result.unwrap_err()
-        )
+    if let Err(error) = result {
+        warn!("Error processing ingester {}: {}", name, error)
     }
 }
 
@@ -38,11 +43,14 @@ async fn process(ingester_config: Ingester, mut config: Config, index: Index) ->
         return Ok(());
     }
 
-    let start_time = time::Instant::now();
+    let start_time = Instant::now();
 
     match ingester_config.ingester_type.as_str() {
         "rss" => rss_ingester::process_rss(ingester_config, config.clone(), index).await,
         "wikipedia" => wikipedia::process_wikipedia(ingester_config, config.clone(), index).await,
+        "sitemap" => {
+            sitemap_ingester::process_sitemap(ingester_config, config.clone(), index).await
+        }
         "spider" => process_spider(ingester_config, config.clone(), index).await,
         a => Err(Error::UnknownIngester(a.to_string())),
     }?;
@@ -57,11 +65,49 @@ async fn process(ingester_config: Ingester, mut config: Config, index: Index) ->
     config.save()?;
 
     let duration = start_time.elapsed();
-    info!("Processing {} took {}", &name, duration);
+    info!("Processing {} took {:?}", &name, duration);
 
     Ok(())
 }
 
+/// What came of fetching a page
+enum Fetched {
+    /// robots.txt doesn't let us fetch it
+    Disallowed,
+    /// it hasn't changed since we last fetched it
+    Unchanged,
+    /// the page's content, and the headers it came with so its validators can be saved once it is indexed
+    Document(Box<Document>, HeaderMap),
+}
+
+/// Fetch a page and pull out its content, honouring any robots meta tags or headers it has. If the page is already
+/// indexed only fetch it if it has changed.
+async fn fetch_document(
+    client: &Client,
+    index: &Index,
+    url: &Url,
+    indexed: bool,
+) -> Result<Fetched, Error> {
+    if !robots_text::check_robots_file(client, url).await? {
+        return Ok(Fetched::Disallowed);
+    }
+    let fetched = if indexed {
+        let validators = validators::load(index, url)?;
+        match web_client::get_page_if_changed(client, url, validators.as_ref()).await? {
+            Some(fetched) => fetched,
+            None => return Ok(Fetched::Unchanged),
+        }
+    } else {
+        web_client::get_page(client, url).await?
+    };
+    let mut document = html::parse(&String::from_utf8_lossy(&fetched.body), url);
+    document.robots.add_headers(&fetched.headers);
+    if document.robots.nofollow {
+        document.links.clear();
+    }
+    Ok(Fetched::Document(Box::new(document), fetched.headers))
+}
+
 async fn process_spider(
     _ingester_config: Ingester,
     _config: Config,
@@ -4,12 +4,19 @@ use crate::data::Page;
 use crate::error::Error;
 use crate::index_sled::Index;
 
-use bytes::Buf;
 use log::info;
+use log::warn;
+use quick_xml::events::Event;
 use rss::Channel;
+use serde::Deserialize;
 use url::Url;
 
+use crate::crawler::html;
+use crate::crawler::html::Document;
+use crate::crawler::ingesters::fetch_document;
+use crate::crawler::ingesters::Fetched;
 use crate::crawler::robots_text;
+use crate::crawler::validators;
 use crate::crawler::web_client;
 
 pub(crate) async fn process_rss(
@@ -17,6 +24,8 @@ pub(crate) async fn process_rss(
     config: Config,
     index: Index,
 ) -> Result<(), Error> {
+    let max_document_size = ingester_config.max_document_size()?;
+    let deep = ingester_config.deep()?;
     let base_url = match ingester_config.base_url {
         Some(u) => u,
         None => return Err(Error::MissingBaseUrl),
@@ -37,41 +46,310 @@ pub(crate) async fn process_rss(
     }
     info!("Allowed to index {base_url} by robots.txt");
 
-    let rss_bytes = web_client::get_url(&client, &target_url).await?;
-    let channel = Channel::read_from(rss_bytes.reader())?;
+    let feed_validators = validators::load(&index, &target_url)?;
+    let feed = match web_client::get_page_if_changed(&client, &target_url, feed_validators.as_ref())
+        .await?
+    {
+        Some(feed) => feed,
+        None => {
+            info!("{base_url} has not changed since it was last read");
+            return Ok(());
+        }
+    };
+    let items = read_items(&feed.body)?;
 
-    for item in channel.items().iter() {
+    for item in items.into_iter() {
+        let title = item.title.unwrap_or_else(|| "No title".to_string());
         info!(
             "found item {} with url: {}",
-            item.title().unwrap_or("no title"),
-            item.link().unwrap_or("No link")
+            title,
+            item.link.as_deref().unwrap_or("No link")
         );
 
-        if item.link().is_none() {
-            info!(
-                "skipping '{}' as it does not have a link",
-                item.title().unwrap_or("No title")
-            );
-            continue;
-        }
+        let link = match item.link {
+            Some(l) => l,
+            None => {
+                info!("skipping '{title}' as it does not have a link");
+                continue;
+            }
+        };
+
+        // links are allowed to be relative to the feed
+        let url = target_url.join(&link)?;
+        // item content is usually html, but can be plain text
+        let content = item
+            .content
+            .map(|c| html::extract_text(&c, &url))
+            .filter(|c| !c.is_empty())
+            .unwrap_or_else(|| "no content".to_string());
 
-        let url = Url::parse(item.link().unwrap())?;
         // Create a page object
-        let page = Page {
+        let mut page = Page {
             url,
-            title: item.title().unwrap_or("No title").to_string(),
-            content: item
-                .content()
-                .unwrap_or(item.description().unwrap_or("no content"))
-                .to_string(),
+            title,
+            content,
+            ingester: ingester_config.name.clone(),
+            links: Vec::new(),
         };
 
+        let existing = index.find_url(page.url.as_str())?;
+        if let Some((_, existing)) = existing.as_ref() {
+            if existing.last_index + ingester_config.update_interval
+                > time::OffsetDateTime::now_utc()
+            {
+                info!(
+                    "Last indexed {} at {} its too soon to do it again.",
+                    page.url, existing.last_index
+                );
+                continue;
+            }
+        }
+
+        let mut article_headers = None;
+        if deep {
+            match fetch_document(&client, &index, &page.url, existing.is_some()).await {
+                Ok(Fetched::Document(article, _)) if article.robots.noindex => {
+                    info!("{} asks not to be indexed", page.url);
+                    if index.delete_page(page.url.as_str())? {
+                        info!("Removed {} from the index", page.url);
+                    }
+                    continue;
+                }
+                Ok(Fetched::Document(article, headers)) => {
+                    article_headers = Some(headers);
+                    use_article(&mut page, *article)
+                }
+                Ok(Fetched::Unchanged) => {
+                    info!("{} has not changed since it was indexed", page.url);
+                    index.touch_page(page.url.as_str())?;
+                    continue;
+                }
+                Ok(Fetched::Disallowed) => {
+                    info!(
+                        "robots.txt disallows {}, indexing the feed summary instead",
+                        page.url
+                    )
+                }
+                Err(e) => warn!(
+                    "Could not fetch {}, indexing the feed summary instead: {e}",
+                    page.url
+                ),
+            }
+        }
+
         // add page to the index
         index
-            .add_page(&page, ingester_config.update_interval)
+            .add_page(&page, ingester_config.update_interval, max_document_size)
             .await?;
+        if let Some(headers) = article_headers {
+            validators::save(&index, &page.url, &ingester_config.name, &headers)?;
+        }
     }
 
+    // only now everything in the feed has been indexed can we skip it next time if it hasn't changed
+    validators::save(&index, &target_url, &ingester_config.name, &feed.headers)?;
     info!("Done processing rss feed {}", ingester_config.name);
     Ok(())
 }
+
+/// Index the article rather than the feed's summary of it. The page stays under the feed's link rather than the
+/// article's canonical url, as the link is all we have to find it in the index again before fetching it.
+fn use_article(page: &mut Page, article: Document) {
+    if !article.text.is_empty() {
+        page.content = article.text;
+    }
+    page.links = article.links;
+}
+
+/// An item from a feed, whichever format the feed is in
+#[derive(Debug)]
+struct FeedItem {
+    title: Option<String>,
+    link: Option<String>,
+    content: Option<String>,
+}
+
+const BYTE_ORDER_MARK: &[u8] = "\u{feff}".as_bytes();
+
+#[derive(Debug, PartialEq)]
+enum FeedFormat {
+    /// Any version of RSS. The rss crate handles 0.9x, 1.0 and 2.0
+    Rss,
+    Atom,
+    Json,
+}
+
+/// Work out what format a feed is from how it starts, rather than trusting the content type servers send.
+fn detect_format(bytes: &[u8]) -> Result<FeedFormat, Error> {
+    let text = bytes.strip_prefix(BYTE_ORDER_MARK).unwrap_or(bytes);
+    if text.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') {
+        return Ok(FeedFormat::Json);
+    }
+
+    // the name of the root element tells the xml formats apart
+    let mut reader = quick_xml::Reader::from_reader(bytes);
+    let mut buffer = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buffer)? {
+            Event::Start(element) | Event::Empty(element) => {
+                return Ok(match element.local_name().into_inner() {
+                    b"feed" => FeedFormat::Atom,
+                    _ => FeedFormat::Rss,
+                });
+            }
+            Event::Eof => return Err(Error::InvalidFeed("feed is empty".to_string())),
+            _ => buffer.clear(),
+        }
+    }
+}
+
+fn read_items(bytes: &[u8]) -> Result<Vec<FeedItem>, Error> {
+    let format = detect_format(bytes)?;
+    info!("Reading {format:?} feed");
+    match format {
+        FeedFormat::Rss => rss_items(bytes),
+        FeedFormat::Atom => atom_items(bytes),
+        FeedFormat::Json => json_items(bytes),
+    }
+}
+
+fn rss_items(bytes: &[u8]) -> Result<Vec<FeedItem>, Error> {
+    let channel = Channel::read_from(bytes)?;
+    Ok(channel
+        .items()
+        .iter()
+        .map(|item| FeedItem {
+            title: item.title().map(str::to_string),
+            link: item.link().map(str::to_string),
+            content: item.content().or(item.description()).map(str::to_string),
+        })
+        .collect())
+}
+
+fn atom_items(bytes: &[u8]) -> Result<Vec<FeedItem>, Error> {
+    let feed = atom_syndication::Feed::read_from(bytes)?;
+    Ok(feed
+        .entries()
+        .iter()
+        .map(|entry| {
+            // the alternate link is the entry itself, others are things like comments or enclosures
+            let link = entry
+                .links()
+                .iter()
+                .find(|l| l.rel() == "alternate")
+                .or(entry.links().first())
+                .map(|l| l.href().to_string());
+            let content = entry
+                .content()
+                .and_then(|c| c.value())
+                .or(entry.summary().map(|s| s.as_str()))
+                .map(str::to_string);
+            FeedItem {
+                title: Some(entry.title().as_str().to_string()),
+                link,
+                content,
+            }
+        })
+        .collect())
+}
+
+/// The parts of a JSON Feed (https://www.jsonfeed.org/version/1.1/) we use
+#[derive(Deserialize)]
+struct JsonFeed {
+    version: String,
+    #[serde(default)]
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Deserialize)]
+struct JsonFeedItem {
+    url: Option<String>,
+    external_url: Option<String>,
+    title: Option<String>,
+    content_text: Option<String>,
+    content_html: Option<String>,
+    summary: Option<String>,
+}
+
+fn json_items(bytes: &[u8]) -> Result<Vec<FeedItem>, Error> {
+    // serde_json doesn't skip a byte order mark
+    let bytes = bytes.strip_prefix(BYTE_ORDER_MARK).unwrap_or(bytes);
+    let feed: JsonFeed = serde_json::from_slice(bytes)?;
+    if !feed.version.starts_with("https://jsonfeed.org/version/") {
+        return Err(Error::InvalidFeed(format!(
+            "unknown json feed version {}",
+            feed.version
+        )));
+    }
+    Ok(feed
+        .items
+        .into_iter()
+        .map(|item| FeedItem {
+            title: item.title,
+            link: item.url.or(item.external_url),
+            content: item.content_text.or(item.content_html).or(item.summary),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crawler::ingesters::rss_ingester::detect_format;
+    use crate::crawler::ingesters::rss_ingester::read_items;
+    use crate::crawler::ingesters::rss_ingester::FeedFormat;
+
+    const RSS: &str = r#"<?xml version="1.0"?>
+<rss version="2.0"><channel><title>Otters</title><link>http://example.com/</link><description>d</description>
+<item><title>Sea otters</title><link>http://example.com/sea</link><description>They float</description></item>
+</channel></rss>"#;
+
+    const RSS_1: &str = r#"<?xml version="1.0"?>
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#" xmlns="http://purl.org/rss/1.0/">
+<channel rdf:about="http://example.com/"><title>Otters</title><link>http://example.com/</link><description>d</description></channel>
+<item rdf:about="http://example.com/river"><title>River otters</title><link>http://example.com/river</link><description>They swim</description></item>
+</rdf:RDF>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<!-- a comment before the root -->
+<feed xmlns="http://www.w3.org/2005/Atom"><title>Otters</title><id>urn:otters</id><updated>2026-01-01T00:00:00Z</updated>
+<entry><title>Giant otters</title><id>urn:giant</id><updated>2026-01-01T00:00:00Z</updated>
+<link rel="replies" href="/giant/comments"/><link href="/giant"/><summary>They are big</summary></entry>
+</feed>"#;
+
+    const JSON: &str = r#"{"version": "https://jsonfeed.org/version/1.1", "title": "Otters", "items": [
+{"id": "1", "url": "http://example.com/small", "title": "Small otters", "content_html": "<p>They are small</p>"},
+{"id": "2", "title": "No link"}]}"#;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect_format(RSS.as_bytes()).unwrap(), FeedFormat::Rss);
+        assert_eq!(detect_format(RSS_1.as_bytes()).unwrap(), FeedFormat::Rss);
+        assert_eq!(detect_format(ATOM.as_bytes()).unwrap(), FeedFormat::Atom);
+        assert_eq!(
+            detect_format(format!("\u{feff}  {JSON}").as_bytes()).unwrap(),
+            FeedFormat::Json
+        );
+        assert!(detect_format(b"").is_err());
+    }
+
+    #[test]
+    fn test_read_items() {
+        let rss = read_items(RSS.as_bytes()).unwrap();
+        assert_eq!(rss[0].link.as_deref(), Some("http://example.com/sea"));
+        assert_eq!(rss[0].content.as_deref(), Some("They float"));
+
+        let rss_1 = read_items(RSS_1.as_bytes()).unwrap();
+        assert_eq!(rss_1[0].title.as_deref(), Some("River otters"));
+
+        let atom = read_items(ATOM.as_bytes()).unwrap();
+        assert_eq!(atom[0].title.as_deref(), Some("Giant otters"));
+        assert_eq!(atom[0].link.as_deref(), Some("/giant"));
+        assert_eq!(atom[0].content.as_deref(), Some("They are big"));
+
+        let json = read_items(JSON.as_bytes()).unwrap();
+        assert_eq!(json.len(), 2);
+        assert_eq!(json[0].link.as_deref(), Some("http://example.com/small"));
+        assert_eq!(json[0].content.as_deref(), Some("<p>They are small</p>"));
+        assert!(json[1].link.is_none());
+    }
+}
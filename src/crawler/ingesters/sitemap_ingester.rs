@@ -0,0 +1,538 @@
+//! Indexes the pages a site lists in its sitemaps, rather than finding them by following links.
+//!
+//! The ingester's `base_url` is either a sitemap, or the site's home page in which case the sitemaps are found from
+//! its robots.txt, falling back to `/sitemap.xml`. Sitemap index files, gzipped sitemaps and plain text sitemaps are
+//! all understood. Pages that have been indexed since their `<lastmod>` time are not fetched again. Every sitemap is
+//! read on every run though, whatever its `<lastmod>` in the index says, as a page that failed last time or was
+//! deleted from the index since needs to be found again.
+
+use std::collections::HashSet;
+use std::io::Read;
+
+use flate2::read::GzDecoder;
+use log::debug;
+use log::info;
+use log::warn;
+use quick_xml::events::Event;
+use reqwest::Client;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use url::Url;
+
+use crate::config::Config;
+use crate::config::Ingester;
+use crate::crawler::ingesters::fetch_document;
+use crate::crawler::ingesters::Fetched;
+use crate::crawler::robots_cache;
+use crate::crawler::robots_text;
+use crate::crawler::validators;
+use crate::crawler::web_client;
+use crate::data::Page;
+use crate::error::Error;
+use crate::index_sled::Index;
+
+/// The sitemap protocol limits sitemaps to 50MB once uncompressed
+const MAX_SITEMAP_SIZE: u64 = 50 * 1024 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// A page or sitemap listed in a sitemap
+#[derive(Debug, PartialEq)]
+struct SitemapEntry {
+    loc: String,
+    lastmod: Option<time::OffsetDateTime>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Sitemap {
+    /// a sitemap index, listing other sitemaps
+    Index(Vec<SitemapEntry>),
+    /// a list of pages
+    Pages(Vec<SitemapEntry>),
+}
+
+pub(crate) async fn process_sitemap(
+    ingester_config: Ingester,
+    config: Config,
+    index: Index,
+) -> Result<(), Error> {
+    let max_document_size = ingester_config.max_document_size()?;
+    let base_url = match ingester_config.base_url.as_deref() {
+        Some(u) => Url::parse(u)?,
+        None => return Err(Error::MissingBaseUrl),
+    };
+
+    info!(
+        "Processing sitemaps! {} at {}",
+        ingester_config.name, &base_url
+    );
+
+    let client = web_client::get_client(&config)?;
+
+    // sitemaps still to read, and whether they came from an index
+    let mut queue: Vec<(Url, bool)> = if is_site_root(&base_url) {
+        discover_sitemaps(&client, &base_url).await?
+    } else {
+        vec![base_url]
+    }
+    .into_iter()
+    .map(|url| (url, false))
+    .collect();
+    let mut seen: HashSet<Url> = queue.iter().map(|(url, _)| url.clone()).collect();
+
+    while let Some((sitemap_url, from_index)) = queue.pop() {
+        if !robots_text::check_robots_file(&client, &sitemap_url).await? {
+            info!("robots.txt disallows {sitemap_url}, skipping it");
+            continue;
+        }
+        let sitemap = match fetch_sitemap(&client, &sitemap_url).await {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Could not read sitemap {sitemap_url}: {e}");
+                continue;
+            }
+        };
+
+        match sitemap {
+            // indexes can't list other indexes, so only follow the ones we were given
+            Sitemap::Index(_) if from_index => {
+                warn!("Sitemap index {sitemap_url} is listed in another index, skipping it")
+            }
+            Sitemap::Index(entries) => {
+                info!("{sitemap_url} lists {} sitemaps", entries.len());
+                for entry in entries {
+                    match sitemap_url.join(&entry.loc) {
+                        Ok(url) if seen.insert(url.clone()) => queue.push((url, true)),
+                        Ok(_) => {}
+                        Err(e) => warn!("Skipping sitemap {} from {sitemap_url}: {e}", entry.loc),
+                    }
+                }
+            }
+            Sitemap::Pages(entries) => {
+                info!("{sitemap_url} lists {} pages", entries.len());
+                for entry in entries {
+                    let url = match sitemap_url.join(&entry.loc) {
+                        Ok(url) => url,
+                        Err(e) => {
+                            warn!("Skipping page {} from {sitemap_url}: {e}", entry.loc);
+                            continue;
+                        }
+                    };
+                    if let Err(e) = process_page(
+                        &client,
+                        &index,
+                        &ingester_config,
+                        url,
+                        entry.lastmod,
+                        max_document_size,
+                    )
+                    .await
+                    {
+                        warn!("Could not index {}: {e}", entry.loc);
+                    }
+                }
+            }
+        }
+    }
+
+    info!("Done processing sitemaps {}", ingester_config.name);
+    Ok(())
+}
+
+/// Fetch a page from a sitemap and index it, unless it hasn't changed since we last did.
+async fn process_page(
+    client: &Client,
+    index: &Index,
+    ingester_config: &Ingester,
+    url: Url,
+    lastmod: Option<time::OffsetDateTime>,
+    max_document_size: usize,
+) -> Result<(), Error> {
+    let existing = index.find_url(url.as_str())?;
+    if let Some((_, existing)) = existing.as_ref() {
+        let unchanged = match lastmod {
+            Some(lastmod) => lastmod <= existing.last_index,
+            // without a time from the sitemap, go back to pages as often as the ingester runs
+            None => {
+                existing.last_index + ingester_config.update_interval
+                    > time::OffsetDateTime::now_utc()
+            }
+        };
+        if unchanged {
+            debug!("{url} hasn't changed since it was indexed");
+            return Ok(());
+        }
+    }
+
+    let (document, headers) = match fetch_document(client, index, &url, existing.is_some()).await? {
+        Fetched::Document(document, headers) => (document, headers),
+        Fetched::Unchanged => {
+            debug!("{url} has not changed since it was indexed");
+            index.touch_page(url.as_str())?;
+            return Ok(());
+        }
+        Fetched::Disallowed => {
+            info!("robots.txt disallows {url}, skipping it");
+            return Ok(());
+        }
+    };
+    if document.robots.noindex {
+        info!("{url} asks not to be indexed");
+        if index.delete_page(url.as_str())? {
+            info!("Removed {url} from the index");
+        }
+        return Ok(());
+    }
+
+    let page = Page {
+        title: document.title.unwrap_or_else(|| url.to_string()),
+        url: url.clone(),
+        content: document.text,
+        ingester: ingester_config.name.clone(),
+        links: document.links,
+    };
+    // we have already decided the page needs indexing again
+    index
+        .add_page(&page, time::Duration::ZERO, max_document_size)
+        .await?;
+    validators::save(index, &url, &ingester_config.name, &headers)
+}
+
+/// True if a url is the home page of a site rather than a sitemap
+fn is_site_root(url: &Url) -> bool {
+    url.path() == "/" && url.query().is_none()
+}
+
+/// Find a site's sitemaps from its robots.txt, or guess where it is if it doesn't say.
+async fn discover_sitemaps(client: &Client, site: &Url) -> Result<Vec<Url>, Error> {
+    let robots_url = robots_cache::robots_url(site)?;
+    let sitemaps = robots_cache::rules_for(client, site)
+        .await?
+        .sitemaps(&robots_url);
+    if sitemaps.is_empty() {
+        info!("robots.txt for {site} doesn't list any sitemaps, trying /sitemap.xml");
+        return Ok(vec![site.join("/sitemap.xml")?]);
+    }
+    Ok(sitemaps)
+}
+
+async fn fetch_sitemap(client: &Client, url: &Url) -> Result<Sitemap, Error> {
+    // a gzipped sitemap is smaller than it will be once unzipped, so this limit covers both
+    let bytes = web_client::get_url_limited(client, url, MAX_SITEMAP_SIZE).await?;
+    read_sitemap(&decompress(&bytes)?)
+}
+
+/// Unzip a sitemap if it is gzipped. Checks the content rather than the name, as servers often unzip files called
+/// `.xml.gz` for us, and some zip files that aren't.
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+    if !bytes.starts_with(GZIP_MAGIC) {
+        return Ok(bytes.to_vec());
+    }
+    let mut result = Vec::new();
+    GzDecoder::new(bytes)
+        .take(MAX_SITEMAP_SIZE)
+        .read_to_end(&mut result)?;
+    Ok(result)
+}
+
+fn read_sitemap(bytes: &[u8]) -> Result<Sitemap, Error> {
+    let text = String::from_utf8_lossy(bytes);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if !text.starts_with('<') {
+        return Ok(Sitemap::Pages(read_text_sitemap(text)));
+    }
+
+    let mut reader = quick_xml::Reader::from_str(text);
+    let mut buffer = Vec::new();
+    let mut is_index = false;
+    let mut entries = Vec::new();
+    // the element we are in the text of, and the entry we are building
+    let mut field: Option<Vec<u8>> = None;
+    let mut loc: Option<String> = None;
+    let mut lastmod: Option<time::OffsetDateTime> = None;
+
+    loop {
+        match reader.read_event_into(&mut buffer)? {
+            Event::Start(element) => match element.local_name().into_inner() {
+                b"sitemapindex" => is_index = true,
+                b"url" | b"sitemap" => {
+                    loc = None;
+                    lastmod = None;
+                }
+                name => field = Some(name.to_vec()),
+            },
+            Event::Text(text) => add_field(&field, &text.unescape()?, &mut loc, &mut lastmod),
+            Event::CData(data) => add_field(
+                &field,
+                &String::from_utf8_lossy(&data),
+                &mut loc,
+                &mut lastmod,
+            ),
+            Event::End(element) => match element.local_name().into_inner() {
+                b"url" | b"sitemap" => {
+                    if let Some(loc) = loc.take() {
+                        entries.push(SitemapEntry { loc, lastmod });
+                    }
+                }
+                _ => field = None,
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+
+    Ok(if is_index {
+        Sitemap::Index(entries)
+    } else {
+        Sitemap::Pages(entries)
+    })
+}
+
+fn add_field(
+    field: &Option<Vec<u8>>,
+    text: &str,
+    loc: &mut Option<String>,
+    lastmod: &mut Option<time::OffsetDateTime>,
+) {
+    let text = text.trim();
+    match field.as_deref() {
+        Some(b"loc") if !text.is_empty() => *loc = Some(text.to_string()),
+        Some(b"lastmod") => *lastmod = parse_lastmod(text),
+        _ => {}
+    }
+}
+
+/// A text sitemap is just a list of urls, one per line
+fn read_text_sitemap(text: &str) -> Vec<SitemapEntry> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| SitemapEntry {
+            loc: line.to_string(),
+            lastmod: None,
+        })
+        .collect()
+}
+
+/// Sitemaps use W3C datetimes, which can be anything from just a year to a full timestamp, and can leave off the
+/// seconds. Anything without a time zone is taken to be UTC.
+fn parse_lastmod(text: &str) -> Option<time::OffsetDateTime> {
+    if let Ok(datetime) = time::OffsetDateTime::parse(text, &Rfc3339) {
+        return Some(datetime);
+    }
+    if let Some((date, time_part)) = text.split_once('T') {
+        // hh:mm followed by a time zone, without the seconds
+        if time_part.len() > 5 && time_part.as_bytes()[2] == b':' && time_part.is_char_boundary(5) {
+            let (hours_minutes, zone) = time_part.split_at(5);
+            let with_seconds = format!("{date}T{hours_minutes}:00{zone}");
+            return time::OffsetDateTime::parse(&with_seconds, &Rfc3339).ok();
+        }
+        return None;
+    }
+
+    let date = match text.len() {
+        4 => format!("{text}-01-01"),
+        7 => format!("{text}-01"),
+        _ => text.to_string(),
+    };
+    time::Date::parse(&date, format_description!("[year]-[month]-[day]"))
+        .ok()
+        .map(|date| date.midnight().assume_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use url::Url;
+
+    use crate::config::Ingester;
+    use crate::crawler::ingesters::sitemap_ingester::decompress;
+    use crate::crawler::ingesters::sitemap_ingester::parse_lastmod;
+    use crate::crawler::ingesters::sitemap_ingester::process_page;
+    use crate::crawler::ingesters::sitemap_ingester::read_sitemap;
+    use crate::crawler::ingesters::sitemap_ingester::Sitemap;
+    use crate::crawler::ingesters::sitemap_ingester::SitemapEntry;
+    use crate::crawler::web_client;
+    use crate::error::Error;
+    use crate::index_sled::Index;
+
+    /// Serve a page with an ETag, answering 304 when asked for it with that ETag. Returns the url of the page and a
+    /// count of the 304s sent.
+    async fn serve_page() -> (Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/page", listener.local_addr().unwrap())).unwrap();
+        let not_modified = Arc::new(AtomicUsize::new(0));
+        let count = not_modified.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let response = if request.starts_with("get /robots.txt") {
+                    "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                        .to_string()
+                } else if request.contains("if-none-match: \"v1\"") {
+                    count.fetch_add(1, Ordering::SeqCst);
+                    "HTTP/1.1 304 Not Modified\r\netag: \"v1\"\r\nconnection: close\r\n\r\n"
+                        .to_string()
+                } else {
+                    let body = "<html><head><title>Apples</title></head><body>apples and pears</body></html>";
+                    format!(
+                        "HTTP/1.1 200 OK\r\netag: \"v1\"\r\ncontent-type: text/html\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, not_modified)
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_page() {
+        let (url, not_modified) = serve_page().await;
+        let index = Index::temporary().unwrap();
+        let client = reqwest::Client::new();
+        let ingester = Ingester {
+            name: "test".to_string(),
+            ingester_type: "sitemap".to_string(),
+            update_interval: time::Duration::ZERO,
+            base_url: None,
+            last_update: time::OffsetDateTime::UNIX_EPOCH,
+            options: HashMap::new(),
+            collection: None,
+        };
+        let process = || process_page(&client, &index, &ingester, url.clone(), None, usize::MAX);
+
+        process().await.unwrap();
+        let (_, first) = index.find_url(url.as_str()).unwrap().unwrap();
+        assert_eq!(first.title, "Apples");
+        assert_eq!(not_modified.load(Ordering::SeqCst), 0);
+
+        // the server says it hasn't changed, so it is only marked as indexed again
+        process().await.unwrap();
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+        let (_, second) = index.find_url(url.as_str()).unwrap().unwrap();
+        assert!(second.last_index > first.last_index);
+        assert_eq!(second.first_index, first.first_index);
+
+        // once deleted it is fetched in full rather than being told it hasn't changed
+        index.delete_page(url.as_str()).unwrap();
+        process().await.unwrap();
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+        assert!(index.find_url(url.as_str()).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_size_limit() {
+        let (url, _) = serve_page().await;
+        let client = reqwest::Client::new();
+        assert!(matches!(
+            web_client::get_url_limited(&client, &url, 10).await,
+            Err(Error::ResponseTooLarge(_, 10))
+        ));
+        assert!(web_client::get_url_limited(&client, &url, 1000)
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_read_urlset() {
+        let input = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url>
+    <loc>https://example.com/?a=1&amp;b=2</loc>
+    <lastmod>2024-03-01</lastmod>
+    <changefreq>weekly</changefreq>
+  </url>
+  <url><loc><![CDATA[https://example.com/about]]></loc></url>
+  <url><lastmod>2024-03-01</lastmod></url>
+</urlset>"#;
+        assert_eq!(
+            read_sitemap(input.as_bytes()).unwrap(),
+            Sitemap::Pages(vec![
+                SitemapEntry {
+                    loc: "https://example.com/?a=1&b=2".to_string(),
+                    lastmod: Some(time::macros::datetime!(2024-03-01 00:00 UTC)),
+                },
+                SitemapEntry {
+                    loc: "https://example.com/about".to_string(),
+                    lastmod: None,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_read_index() {
+        let input = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap>
+    <loc>https://example.com/sitemap-docs.xml.gz</loc>
+    <lastmod>2024-03-01T10:30+01:00</lastmod>
+  </sitemap>
+</sitemapindex>"#;
+        assert_eq!(
+            read_sitemap(input.as_bytes()).unwrap(),
+            Sitemap::Index(vec![SitemapEntry {
+                loc: "https://example.com/sitemap-docs.xml.gz".to_string(),
+                lastmod: Some(time::macros::datetime!(2024-03-01 09:30 UTC)),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_read_text() {
+        let input = "https://example.com/a\n\n  https://example.com/b\n";
+        match read_sitemap(input.as_bytes()).unwrap() {
+            Sitemap::Pages(entries) => assert_eq!(entries.len(), 2),
+            s => panic!("expected pages, got {s:?}"),
+        }
+    }
+
+    #[test]
+    fn test_decompress() {
+        let input = b"<urlset></urlset>";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(input).unwrap();
+        let zipped = encoder.finish().unwrap();
+
+        assert_eq!(decompress(&zipped).unwrap(), input);
+        assert_eq!(decompress(input).unwrap(), input);
+    }
+
+    #[test]
+    fn test_parse_lastmod() {
+        assert_eq!(
+            parse_lastmod("2024-03-01T10:30:15.5Z"),
+            Some(time::macros::datetime!(2024-03-01 10:30:15.5 UTC))
+        );
+        assert_eq!(
+            parse_lastmod("2024"),
+            Some(time::macros::datetime!(2024-01-01 00:00 UTC))
+        );
+        assert_eq!(
+            parse_lastmod("2024-05"),
+            Some(time::macros::datetime!(2024-05-01 00:00 UTC))
+        );
+        assert_eq!(parse_lastmod("yesterday"), None);
+    }
+}
@@ -1,6 +1,7 @@
 use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
+use std::time::Instant;
 
 use crate::config::Config;
 use crate::config::Ingester;
@@ -66,11 +67,11 @@ pub(crate) async fn process_wikipedia(
     let mut workers: Vec<JoinHandle<()>> = Vec::new();
     info!("Starting {} page workers", config.crawler.workers);
     for _worker in 0..config.crawler.workers {
-        workers.push(tokio::spawn(page_processor(config.clone(), index.clone(), rx.clone())))
+        workers.push(tokio::spawn(page_processor(config.clone(), ingester_config.clone(), index.clone(), rx.clone())))
     }
     info!("Starting page feed");
     // Now load up the queue
-    while let Some(page) = read_page(&mut xml_reader)? {
+    while let Some(page) = read_page(&mut xml_reader, &ingester_config.name)? {
         let result = tx.send_async(page).await;
         if let Err(error) = result {
             warn!("Error sending page into channel: {:?}", error);
@@ -126,18 +127,18 @@ async fn download_archive(client: &Client, update_date : &time::OffsetDateTime)
     Ok(target_path)
 }
 
-async fn page_processor(config: Config, index:Index, rx: Receiver<Page>) {
+async fn page_processor(config: Config, ingester_config: Ingester, index:Index, rx: Receiver<Page>) {
     while let Ok(page) = rx.clone().into_recv_async().await {
-        let start_time = time::Instant::now();
+        let start_time = Instant::now();
         let title = page.title.clone();
         info!("processing page: {title}");
         
-        let result = process_page_inner(&config, page, &index).await;
+        let result = process_page_inner(&config, &ingester_config, page, &index).await;
         if let Err(error) = result {
             warn!("Error Processing page {}: {}", title, error);
             panic!("errored processing wikipedia page");
         }
-        info!("done processing page {}! took {}", title, start_time.elapsed());
+        info!("done processing page {}! took {:?}", title, start_time.elapsed());
     }
 }
 
@@ -153,7 +154,7 @@ async fn page_processor(config: Config, index:Index, rx: Receiver<Page>) {
 //     info!("done processing page {}! took {}", title, start_time.elapsed());
 // }
 
-async fn process_page_inner(config: &Config, page: Page, index: &Index) -> Result<(), Error> {
+async fn process_page_inner(config: &Config, ingester_config: &Ingester, page: Page, index: &Index) -> Result<(), Error> {
 
     // filter out pages that are just redirects and 
     if page.content.starts_with("#REDIRECT") {
@@ -165,7 +166,8 @@ async fn process_page_inner(config: &Config, page: Page, index: &Index) -> Resul
         let stripped_page = strip_page(&page);
 
         // info!("{}", &page.content);
-        match index.add_page(&stripped_page, config.crawler.min_update_interval).await {
+        let max_document_size = ingester_config.max_document_size()?;
+        match index.add_page(&stripped_page, config.crawler.min_update_interval, max_document_size).await {
             Ok(()) => Ok(()),
             Err(e) => {
                 warn!("Could not index page {}: {:?}", stripped_page.title, e);
@@ -176,7 +178,7 @@ async fn process_page_inner(config: &Config, page: Page, index: &Index) -> Resul
     }
 }
 
-fn read_page(xml: &mut quick_xml::Reader<BufReader<MultiBzDecoder<File>>>) -> Result<Option<Page>, Error> {
+fn read_page(xml: &mut quick_xml::Reader<BufReader<MultiBzDecoder<File>>>, ingester: &str) -> Result<Option<Page>, Error> {
     #[derive(Debug)]
     enum State {
         Limbo1,
@@ -239,7 +241,7 @@ fn read_page(xml: &mut quick_xml::Reader<BufReader<MultiBzDecoder<File>>>) -> Re
     }
 
     if let State::Limbo4 { title, text } = state {
-        return Ok(Some(Page { url: create_url(&title)?, title, content: text }));
+        return Ok(Some(Page { url: create_url(&title)?, title, content: text, ingester: ingester.to_string(), links: Vec::new() }));
     }
     Ok(None)
 }
@@ -256,7 +258,7 @@ fn strip_page(page: &Page) -> Page {
     // filter special sections here
     let mut new_content = filter_between(&page.content, '{', '{', '}', '}');
     new_content = filter_between(&new_content, '{', '|', '|', '}');
-    new_content = filter_square_brackets(&new_content);
+    let (mut new_content, targets) = filter_square_brackets(&new_content);
 
     new_content = new_content.replace("==", "");
 
@@ -264,9 +266,38 @@ fn strip_page(page: &Page) -> Page {
         url: page.url.clone(),
         title: page.title.clone(),
         content: new_content,
+        ingester: page.ingester.clone(),
+        links: link_urls(&targets),
     }
 }
 
+// Turn the targets of [[...]] links into urls for the articles they point at. Links to other namespaces (files,
+// categories, other languages) are dropped as they are not articles we index.
+fn link_urls(targets: &[String]) -> Vec<Url> {
+    let mut result: Vec<Url> = Vec::new();
+    for target in targets.iter() {
+        // links to a section of an article count as links to the article
+        let title = target.split('#').next().unwrap_or("").trim();
+        if title.is_empty() || title.contains(':') {
+            continue;
+        }
+
+        // article titles always start with a capital letter, but links don't have to
+        let mut chars = title.chars();
+        let title = match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+            None => continue,
+        };
+
+        if let Ok(url) = create_url(&title) {
+            if !result.contains(&url) {
+                result.push(url);
+            }
+        }
+    }
+    result
+}
+
 
 // Filter a string, removing everything between two instances of start and end
 fn filter_between(content : &str, start1 : char, start2: char, end1: char, end2: char) -> String {
@@ -328,8 +359,10 @@ fn filter_between(content : &str, start1 : char, start2: char, end1: char, end2:
     result
 }
 
-fn filter_square_brackets(content: &str) -> String {
+// Replace [[target|text]] links with their text. Returns the new content and the target of every link found.
+fn filter_square_brackets(content: &str) -> (String, Vec<String>) {
     let mut result : String = String::new();
+    let mut links : Vec<String> = Vec::new();
 
     enum State {
         Outside,
@@ -341,6 +374,8 @@ fn filter_square_brackets(content: &str) -> String {
     let mut state = State::Outside; 
 
     let mut buffer = Vec::new(); 
+    // the target of the link at each level, once we have seen the | that ends it
+    let mut targets : Vec<Option<String>> = Vec::new();
 
     for c in content.chars() {
         match state {
@@ -353,10 +388,13 @@ fn filter_square_brackets(content: &str) -> String {
             },
             State::Starting(i) => {
                 if c == '[' {
-                    if i+1 >= buffer.len() {
+                    // level i+1 uses buffer[i], clear out anything left from the last link at that level
+                    if i >= buffer.len() {
                         buffer.push(String::new());
+                        targets.push(None);
                     } else {
-                        buffer[i+1] = String::new();
+                        buffer[i] = String::new();
+                        targets[i] = None;
                     }
                     state = State::Inside(i+1);
                 } else if i <= 1 {
@@ -379,6 +417,9 @@ fn filter_square_brackets(content: &str) -> String {
                         state = State::Ending(i);
                     }
                     '|' => {
+                        if targets[i-1].is_none() {
+                            targets[i-1] = Some(buffer[i-1].clone());
+                        }
                         buffer[i-1] = String::new();
                     }
                     '[' => {
@@ -392,6 +433,7 @@ fn filter_square_brackets(content: &str) -> String {
             },
             State::Ending(i) => {
                 if c == ']' {
+                    links.push(targets[i-1].take().unwrap_or_else(|| buffer[i-1].clone()));
                     if i <= 1 {
                         result.push_str(&buffer[i-1]);
                         state = State::Outside;
@@ -405,13 +447,14 @@ fn filter_square_brackets(content: &str) -> String {
         }        
     }
 
-    result
+    (result, links)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::crawler::ingesters::wikipedia::filter_between;
     use crate::crawler::ingesters::wikipedia::filter_square_brackets;
+    use crate::crawler::ingesters::wikipedia::link_urls;
 
 
     #[test]
@@ -437,12 +480,26 @@ mod tests {
             ("some text [[an article name]] foo bar", "some text an article name foo bar"),
             ("some text [[an article name|something [[nested]]]] foo bar", "some text something nested foo bar"),
             ("some text [[an article name|something [[with sub bits|nested]]]] foo bar", "some text something nested foo bar"),
+            ("[[first]] and [[second|2nd]] links", "first and 2nd links"),
         ];
 
         for (input, expected) in cases.into_iter() {
-            let result = filter_square_brackets(input);
+            let (result, _) = filter_square_brackets(input);
             assert_eq!(result, expected)
         }
     }
 
+    #[test]
+    fn test_links() {
+        let (_, targets) = filter_square_brackets("see [[otter#Diet|what otters eat]] and [[File:Otter.jpg|thumb|an [[Eurasian otter]]]] or [[river]]");
+        assert_eq!(targets, vec!["otter#Diet", "Eurasian otter", "File:Otter.jpg", "river"]);
+
+        let urls: Vec<String> = link_urls(&targets).iter().map(|u| u.to_string()).collect();
+        assert_eq!(urls, vec![
+            "https://en.wikipedia.org/wiki/Otter",
+            "https://en.wikipedia.org/wiki/Eurasian_otter",
+            "https://en.wikipedia.org/wiki/River",
+        ]);
+    }
+
 }
\ No newline at end of file
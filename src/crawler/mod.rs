@@ -1,39 +1,66 @@
+use std::time::Instant;
+
 use log::info;
+use log::warn;
 
 use crate::config::Config;
 use crate::error::Error;
 use crate::index_sled::Index;
 
+pub mod html;
 pub mod ingesters;
+pub mod politeness;
+pub mod retry;
+pub mod robots_cache;
+pub mod robots_tags;
 pub mod robots_text;
+pub mod validators;
 pub mod web_client;
 
 pub async fn crawler_main() -> Result<(), Error> {
     info!("Crawler starting. Loading config");
     let config = Config::load()?;
+    politeness::configure(&config.crawler);
+    retry::configure(&config.crawler);
 
-    let process_start = time::Instant::now();
-
-    let index = Index::load().await?;
+    let process_start = Instant::now();
 
     // set up crawler engine...
     // build list of processors to handle.
     info!("Creating {} tasks", config.targets.len());
     let mut tasks = Vec::new();
+    let mut indexes: Vec<Index> = Vec::new();
     for ingester in config.targets.iter() {
+        let index = Index::open(ingester.collection()).await?;
         tasks.push(tokio::spawn(ingesters::process_ingester(
             ingester.clone(),
             config.clone(),
             index.clone(),
-        )))
+        )));
+        if !indexes
+            .iter()
+            .any(|i| i.collection_name() == index.collection_name())
+        {
+            indexes.push(index);
+        }
     }
 
     for fut in tasks {
         fut.await?;
     }
 
+    // now everything has been crawled the links between pages can be ranked
+    for index in indexes.into_iter() {
+        let name = index.collection_name().to_string();
+        match tokio::task::spawn_blocking(move || index.update_ranks(false)).await? {
+            Ok(0) => {}
+            Ok(count) => info!("Ranked {count} pages in {name}"),
+            Err(e) => warn!("Could not rank pages in {name}: {e}"),
+        }
+    }
+
     let process_end = process_start.elapsed();
-    info!("processing took: {}", process_end);
+    info!("processing took: {:?}", process_end);
 
     Ok(())
 }
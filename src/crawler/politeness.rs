@@ -0,0 +1,247 @@
+//! Keeps the crawler from hammering any one site, whichever ingesters are fetching from it.
+//!
+//! Every request waits for its turn with the host it is going to. Turns start at least `host_delay` apart, or the
+//! site's `Crawl-delay` if that is longer, and no more than `host_connections` requests to a host are in flight at
+//! once.
+//!
+//! It also keeps track of hosts that keep failing. A request counts as failed once it has used up its retries. After
+//! enough failures in a row nothing more is asked of a host for a while, rather than every page from it failing
+//! slowly, retries and all. Once that time is up the next request is let through to see if it has recovered.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use log::debug;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+use url::Url;
+
+use crate::config::Crawler;
+use crate::error::Error;
+
+/// Settings used if the crawler hasn't been configured, when fetching from the command line for example
+const DEFAULT_DELAY: Duration = Duration::from_secs(1);
+const DEFAULT_CONNECTIONS: usize = 2;
+
+/// Failures in a row before we stop asking a host for anything
+const MAX_FAILURES: u32 = 5;
+
+/// How long to leave a host alone once it has failed too many times
+const FAILED_HOST_WAIT: Duration = Duration::from_secs(10 * 60);
+
+struct Host {
+    connections: Arc<Semaphore>,
+    /// when the next request to this host may start
+    next_start: tokio::sync::Mutex<Instant>,
+    failures: Mutex<Failures>,
+}
+
+#[derive(Debug, Default)]
+struct Failures {
+    /// failures since the last request that worked
+    in_a_row: u32,
+    /// when we can start asking the host for things again, if we have stopped
+    until: Option<Instant>,
+}
+
+struct Scheduler {
+    min_delay: Duration,
+    max_connections: usize,
+    hosts: Mutex<HashMap<String, Arc<Host>>>,
+}
+
+/// Permission to make a request to a host. Hold on to it until the request has finished, and say how it went.
+pub struct Turn {
+    _connection: OwnedSemaphorePermit,
+    host: Arc<Host>,
+}
+
+impl Turn {
+    /// The host answered, even if it wasn't with what we wanted
+    pub fn succeeded(&self) {
+        *self.host.failures.lock().unwrap_or_else(|e| e.into_inner()) = Failures::default();
+    }
+
+    /// The host didn't answer, or had a problem answering
+    pub fn failed(&self) {
+        let mut failures = self.host.failures.lock().unwrap_or_else(|e| e.into_inner());
+        failures.in_a_row += 1;
+        if failures.in_a_row >= MAX_FAILURES {
+            failures.until = Some(Instant::now() + FAILED_HOST_WAIT);
+        }
+    }
+}
+
+static SCHEDULER: OnceLock<Scheduler> = OnceLock::new();
+
+impl Scheduler {
+    fn new(min_delay: Duration, max_connections: usize) -> Self {
+        Scheduler {
+            min_delay,
+            // with no connections allowed nothing would ever be fetched
+            max_connections: max_connections.max(1),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn host(&self, name: &str) -> Arc<Host> {
+        self.hosts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                Arc::new(Host {
+                    connections: Arc::new(Semaphore::new(self.max_connections)),
+                    next_start: tokio::sync::Mutex::new(Instant::now()),
+                    failures: Mutex::new(Failures::default()),
+                })
+            })
+            .clone()
+    }
+
+    async fn wait_turn(&self, url: &Url, crawl_delay: Option<Duration>) -> Result<Turn, Error> {
+        let name = match url.host_str() {
+            Some(host) => host,
+            None => return Err(Error::MissingHost(url.to_string())),
+        };
+        let host = self.host(name);
+        if host.unavailable() {
+            return Err(Error::HostUnavailable(name.to_string()));
+        }
+        let connection = host
+            .connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("host connection semaphores are never closed");
+
+        let delay = crawl_delay.map_or(self.min_delay, |d| d.max(self.min_delay));
+        let start = {
+            let mut next_start = host.next_start.lock().await;
+            let start = (*next_start).max(Instant::now());
+            *next_start = start + delay;
+            start
+        };
+        if start > Instant::now() {
+            debug!(
+                "Waiting {:?} for our turn with {name}",
+                start - Instant::now()
+            );
+            tokio::time::sleep_until(start).await;
+        }
+
+        // it may have failed while we were waiting
+        if host.unavailable() {
+            return Err(Error::HostUnavailable(name.to_string()));
+        }
+        Ok(Turn {
+            _connection: connection,
+            host,
+        })
+    }
+}
+
+impl Host {
+    fn unavailable(&self) -> bool {
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        failures.until.is_some_and(|until| until > Instant::now())
+    }
+}
+
+fn scheduler() -> &'static Scheduler {
+    SCHEDULER.get_or_init(|| Scheduler::new(DEFAULT_DELAY, DEFAULT_CONNECTIONS))
+}
+
+/// Set up the scheduler from the crawler config. Only the first call has any effect, so do this before crawling.
+pub fn configure(config: &Crawler) {
+    let min_delay = Duration::try_from(config.host_delay).unwrap_or(DEFAULT_DELAY);
+    let _ = SCHEDULER.set(Scheduler::new(min_delay, config.host_connections));
+}
+
+/// Wait until we can make a request for url. crawl_delay is how long the site's robots.txt asks us to leave between
+/// requests, if it says.
+pub async fn wait_turn(url: &Url, crawl_delay: Option<Duration>) -> Result<Turn, Error> {
+    scheduler().wait_turn(url, crawl_delay).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+    use url::Url;
+
+    use crate::crawler::politeness::Scheduler;
+    use crate::crawler::politeness::MAX_FAILURES;
+    use crate::error::Error;
+
+    #[tokio::test]
+    async fn test_requests_spaced_out() {
+        let scheduler = Scheduler::new(Duration::from_millis(50), 2);
+        let url = Url::parse("http://example.com/a").unwrap();
+        let other = Url::parse("http://example.org/a").unwrap();
+
+        let start = Instant::now();
+        drop(scheduler.wait_turn(&url, None).await.unwrap());
+        drop(scheduler.wait_turn(&other, None).await.unwrap());
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        drop(scheduler.wait_turn(&url, None).await.unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // a longer crawl delay from the site pushes back the turn after it. That turn can't start before we ask for
+        // it, so the next is at least the crawl delay after we asked.
+        let before = start.elapsed();
+        drop(
+            scheduler
+                .wait_turn(&url, Some(Duration::from_millis(100)))
+                .await
+                .unwrap(),
+        );
+        drop(scheduler.wait_turn(&url, None).await.unwrap());
+        assert!(start.elapsed() >= before + Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_failing_host() {
+        let scheduler = Scheduler::new(Duration::ZERO, 2);
+        let url = Url::parse("http://example.com/a").unwrap();
+
+        for _ in 0..MAX_FAILURES - 1 {
+            scheduler.wait_turn(&url, None).await.unwrap().failed();
+        }
+        // a success resets the count
+        scheduler.wait_turn(&url, None).await.unwrap().succeeded();
+        for _ in 0..MAX_FAILURES - 1 {
+            scheduler.wait_turn(&url, None).await.unwrap().failed();
+        }
+        let turn = scheduler.wait_turn(&url, None).await.unwrap();
+        turn.failed();
+        drop(turn);
+
+        assert!(matches!(
+            scheduler.wait_turn(&url, None).await,
+            Err(Error::HostUnavailable(_))
+        ));
+        let other = Url::parse("http://example.org/a").unwrap();
+        assert!(scheduler.wait_turn(&other, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit() {
+        let scheduler = Scheduler::new(Duration::ZERO, 1);
+        let url = Url::parse("http://example.com/a").unwrap();
+
+        let turn = scheduler.wait_turn(&url, None).await.unwrap();
+        let waiting =
+            tokio::time::timeout(Duration::from_millis(20), scheduler.wait_turn(&url, None));
+        assert!(waiting.await.is_err());
+
+        drop(turn);
+        scheduler.wait_turn(&url, None).await.unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+//! Decides which failed requests are worth trying again, and how long to wait first.
+//!
+//! Dropped connections, timeouts, `429 Too Many Requests` and server errors are retried, waiting twice as long each
+//! time with some randomness so requests that failed together don't all come back together. When a server says how
+//! long to wait with `Retry-After` we do as it asks, unless that is so long we would rather give up until the next
+//! run.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use reqwest::header::HeaderMap;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+
+use crate::config::Crawler;
+
+/// Longest we will wait between retries when working it out ourselves
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Longest we will wait when a server asks with `Retry-After`
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    retries: u32,
+    base_delay: Duration,
+}
+
+static POLICY: OnceLock<RetryPolicy> = OnceLock::new();
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            base_delay: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before trying again after failed_attempts failures, or None to give up. retry_after is what
+    /// the server asked for, if it did.
+    pub fn delay(&self, failed_attempts: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if failed_attempts > self.retries {
+            return None;
+        }
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= MAX_RETRY_AFTER).then_some(retry_after);
+        }
+
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(failed_attempts.saturating_sub(1)))
+            .min(MAX_BACKOFF);
+        // somewhere between half and all of the backoff
+        let jitter = RandomState::new().hash_one(failed_attempts) % 1000;
+        Some(backoff / 2 + backoff / 2 * jitter as u32 / 1000)
+    }
+}
+
+/// The retry policy set up from the crawler config
+pub fn policy() -> RetryPolicy {
+    *POLICY.get_or_init(RetryPolicy::default)
+}
+
+/// Set up retries from the crawler config. Only the first call has any effect, so do this before crawling.
+pub fn configure(config: &Crawler) {
+    let _ = POLICY.set(RetryPolicy {
+        retries: config.retries,
+        base_delay: Duration::try_from(config.retry_delay)
+            .unwrap_or(RetryPolicy::default().base_delay),
+    });
+}
+
+/// True if a response with this status might work if we ask again later
+pub fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// True if a request error is a problem with the connection rather than with what we asked for
+pub fn is_retryable_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout() || error.is_body()
+}
+
+/// How long a response asks us to wait before trying again. `Retry-After` can be a number of seconds or a date.
+pub fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use reqwest::header::HeaderMap;
+    use reqwest::header::HeaderValue;
+    use reqwest::header::RETRY_AFTER;
+    use reqwest::StatusCode;
+
+    use crate::crawler::retry::is_retryable;
+    use crate::crawler::retry::is_retryable_error;
+    use crate::crawler::retry::retry_after;
+    use crate::crawler::retry::RetryPolicy;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            retries: 3,
+            base_delay: Duration::from_secs(2),
+        };
+        for (attempt, full) in [(1, 2), (2, 4), (3, 8)] {
+            let delay = policy.delay(attempt, None).unwrap();
+            let full = Duration::from_secs(full);
+            assert!(
+                delay >= full / 2 && delay <= full,
+                "{delay:?} for attempt {attempt}"
+            );
+        }
+        assert_eq!(policy.delay(4, None), None);
+
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(30))),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(3600))), None);
+    }
+
+    #[test]
+    fn test_retryable() {
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable(StatusCode::NOT_FOUND));
+        assert!(!is_retryable(StatusCode::NOT_MODIFIED));
+    }
+
+    #[tokio::test]
+    async fn test_retryable_error() {
+        let client = reqwest::Client::new();
+        let bad_url = client.get("not a url").build().unwrap_err();
+        assert!(!is_retryable_error(&bad_url));
+
+        // nothing is listening on the port once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let refused = client
+            .get(format!("http://{address}/"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(is_retryable_error(&refused));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers, now), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(120)));
+
+        // 1_700_000_000 is Tue, 14 Nov 2023 22:13:20 GMT
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Tue, 14 Nov 2023 22:14:20 GMT"),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,463 @@
+//! Keeps robots.txt files so each one is fetched about once a day rather than once for every page on the site.
+//!
+//! Files are kept in memory and in sled, so they survive restarts, keyed by the origin of the site they are for. How
+//! long they are kept follows the `Cache-Control` and `Expires` headers they were served with, up to a day. When a
+//! robots.txt can't be fetched RFC 9309 says what to assume instead. If it doesn't exist (a 4xx status) anything is
+//! allowed. If the server is having problems (a 5xx status, or no answer at all) nothing is. Rather than pretending
+//! the site told us that, asking for its rules is an error until we try again a little later, so whatever wanted them
+//! fails and is tried again on its next run. That is only remembered in memory, a restart tries again straight away.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use log::debug;
+use log::info;
+use log::warn;
+use reqwest::header::HeaderMap;
+use reqwest::header::CACHE_CONTROL;
+use reqwest::header::EXPIRES;
+use reqwest::Client;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde::Serialize;
+use url::Url;
+
+use crate::crawler::robots_text::RobotRule;
+use crate::crawler::robots_text::Robots;
+use crate::crawler::web_client;
+use crate::crawler::web_client::USER_AGENT;
+use crate::error::Error;
+use crate::utils::system_root;
+
+/// How long a robots.txt is kept when its headers don't say, and the longest it is kept whatever they say
+const DEFAULT_TTL: time::Duration = time::Duration::hours(24);
+
+/// Shortest time a robots.txt is kept, even when its headers say not to keep it at all. Without this a spider would
+/// fetch it again for every page.
+const MIN_TTL: time::Duration = time::Duration::minutes(10);
+
+/// How long to wait before trying again after failing to fetch a robots.txt because of a server or network problem
+const ERROR_TTL: time::Duration = time::Duration::minutes(10);
+
+/// RFC 9309 only requires the first 500KiB of a robots.txt to be read
+const MAX_ROBOTS_SIZE: usize = 500 * 1024;
+
+/// What we know about a site's robots.txt, as it is stored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum StoredRules {
+    AllowAll,
+    Rules { body: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredRobots {
+    #[serde(with = "time::serde::rfc3339")]
+    expires: time::OffsetDateTime,
+    rules: StoredRules,
+}
+
+#[derive(Debug)]
+enum Rules {
+    AllowAll,
+    Parsed(Robots),
+}
+
+/// The robots.txt rules for a site
+#[derive(Debug)]
+pub struct CachedRobots {
+    expires: time::OffsetDateTime,
+    rules: Rules,
+}
+
+impl CachedRobots {
+    fn from_stored(stored: &StoredRobots) -> Result<Self, Error> {
+        let rules = match &stored.rules {
+            StoredRules::AllowAll => Rules::AllowAll,
+            StoredRules::Rules { body } => {
+                Rules::Parsed(Robots::parse_file(Bytes::from(body.clone()))?)
+            }
+        };
+        Ok(CachedRobots {
+            expires: stored.expires,
+            rules,
+        })
+    }
+
+    /// True if these rules let us fetch the url
+    pub fn allows(&self, url: &Url) -> bool {
+        match &self.rules {
+            Rules::AllowAll => true,
+            Rules::Parsed(robots) => {
+                !matches!(robots.check_url(USER_AGENT, url), Some(RobotRule::Deny(_)))
+            }
+        }
+    }
+
+    /// How long the site asks us to wait between requests, if it says
+    pub fn crawl_delay(&self) -> Option<std::time::Duration> {
+        match &self.rules {
+            Rules::Parsed(robots) => robots.crawl_delay(USER_AGENT),
+            _ => None,
+        }
+    }
+
+    /// The sitemaps the site lists. Relative urls are taken to be relative to robots.txt, and any that make no sense
+    /// are left out.
+    pub fn sitemaps(&self, robots_url: &Url) -> Vec<Url> {
+        match &self.rules {
+            Rules::Parsed(robots) => robots
+                .sitemaps()
+                .iter()
+                .filter_map(|sitemap| robots_url.join(sitemap).ok())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn expired(&self, now: time::OffsetDateTime) -> bool {
+        self.expires <= now
+    }
+}
+
+struct RobotsCache {
+    memory: Mutex<HashMap<String, Arc<CachedRobots>>>,
+    /// sites we couldn't get a robots.txt from, and when to try again
+    failed: Mutex<HashMap<String, time::OffsetDateTime>>,
+    /// None if the cache couldn't be opened on disk, in which case it is only kept in memory
+    db: Option<sled::Db>,
+}
+
+impl RobotsCache {
+    fn lookup(
+        &self,
+        key: &str,
+        now: time::OffsetDateTime,
+    ) -> Result<Option<Arc<CachedRobots>>, Error> {
+        let mut failed = self.failed.lock().unwrap_or_else(|e| e.into_inner());
+        match failed.get(key) {
+            Some(retry_at) if *retry_at > now => {
+                return Err(Error::RobotsUnavailable(key.to_string()))
+            }
+            Some(_) => {
+                failed.remove(key);
+            }
+            None => {}
+        }
+        drop(failed);
+
+        let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cached) = memory.get(key) {
+            if !cached.expired(now) {
+                return Ok(Some(cached.clone()));
+            }
+        }
+
+        let stored = match self.db.as_ref() {
+            Some(db) => db.get(key)?,
+            None => None,
+        };
+        if let Some(value) = stored {
+            let stored: StoredRobots = serde_json::from_slice(&value)?;
+            let cached = Arc::new(CachedRobots::from_stored(&stored)?);
+            if !cached.expired(now) {
+                memory.insert(key.to_string(), cached.clone());
+                return Ok(Some(cached));
+            }
+        }
+        Ok(None)
+    }
+
+    fn insert(&self, key: &str, stored: &StoredRobots) -> Result<Arc<CachedRobots>, Error> {
+        let cached = Arc::new(CachedRobots::from_stored(stored)?);
+        if let Some(db) = self.db.as_ref() {
+            db.insert(key, serde_json::to_vec(stored)?)?;
+        }
+        self.memory
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.to_string(), cached.clone());
+        Ok(cached)
+    }
+
+    /// Remember we couldn't get a robots.txt for the site until retry_at
+    fn insert_failure(&self, key: &str, retry_at: time::OffsetDateTime) {
+        self.failed
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.to_string(), retry_at);
+    }
+}
+
+fn cache() -> &'static RobotsCache {
+    static CACHE: OnceLock<RobotsCache> = OnceLock::new();
+    CACHE.get_or_init(|| {
+        // tests shouldn't leave anything behind in the real cache
+        let db = if cfg!(test) {
+            None
+        } else {
+            match sled::open(system_root().join("robots_index")) {
+                Ok(db) => Some(db),
+                Err(e) => {
+                    warn!("Could not open the robots.txt cache, only keeping it in memory: {e}");
+                    None
+                }
+            }
+        };
+        RobotsCache {
+            memory: Mutex::new(HashMap::new()),
+            failed: Mutex::new(HashMap::new()),
+            db,
+        }
+    })
+}
+
+/// The robots.txt rules for the site url is on, fetching them if we don't have them or they have expired. An error if
+/// the site couldn't tell us what they are, in which case nothing should be fetched from it.
+pub async fn rules_for(client: &Client, url: &Url) -> Result<Arc<CachedRobots>, Error> {
+    let key = url.origin().ascii_serialization();
+    let cache = cache();
+    if let Some(cached) = cache.lookup(&key, time::OffsetDateTime::now_utc())? {
+        debug!("Using cached robots.txt for {key}");
+        return Ok(cached);
+    }
+
+    let robots_url = robots_url(url)?;
+    let (rules, ttl) = match fetch(client, &robots_url).await {
+        Ok(r) => r,
+        // the host is already being left alone for a while, there is nothing to remember
+        Err(Error::HostUnavailable(host)) => return Err(Error::HostUnavailable(host)),
+        Err(e) => {
+            warn!("Could not fetch {robots_url}, not fetching anything from {key} for now: {e}");
+            cache.insert_failure(&key, time::OffsetDateTime::now_utc() + ERROR_TTL);
+            return Err(Error::RobotsUnavailable(key));
+        }
+    };
+    info!("Fetched {robots_url}, keeping it for {ttl}");
+    let stored = StoredRobots {
+        expires: time::OffsetDateTime::now_utc() + ttl,
+        rules,
+    };
+    cache.insert(&key, &stored)
+}
+
+/// Where the robots.txt for the site url is on lives. Only the scheme, host and port matter.
+pub fn robots_url(url: &Url) -> Result<Url, Error> {
+    if url.host_str().is_none() {
+        return Err(Error::MissingHost(url.to_string()));
+    }
+    let mut robots_url = url.clone();
+    robots_url.set_path("/robots.txt");
+    robots_url.set_query(None);
+    robots_url.set_fragment(None);
+    // these can only fail for urls without a host, which we have already ruled out
+    let _ = robots_url.set_username("");
+    let _ = robots_url.set_password(None);
+    Ok(robots_url)
+}
+
+/// Fetch a robots.txt, returning what it tells us and how long to keep it. An error if the server couldn't answer.
+async fn fetch(client: &Client, robots_url: &Url) -> Result<(StoredRules, time::Duration), Error> {
+    // server errors that are still there after retrying come back as errors too
+    let (_turn, response) = web_client::send(client, robots_url, HeaderMap::new(), None).await?;
+
+    let status = response.status();
+    let ttl = ttl(response.headers(), SystemTime::now());
+    match rules_for_status(status)? {
+        Some(rules) => {
+            debug!("{robots_url} returned {status}");
+            Ok((rules, ttl))
+        }
+        None => {
+            let body = response.bytes().await?;
+            let body = &body[..body.len().min(MAX_ROBOTS_SIZE)];
+            let body = String::from_utf8_lossy(body).to_string();
+            Ok((StoredRules::Rules { body }, ttl))
+        }
+    }
+}
+
+/// What to assume about a site from the status its robots.txt was served with. None if the body should be read, an
+/// error if the server is having problems.
+fn rules_for_status(status: StatusCode) -> Result<Option<StoredRules>, Error> {
+    if status.is_success() {
+        Ok(None)
+    } else if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        // being asked to slow down is treated like the server having problems rather than the file not existing
+        Err(Error::Request(status))
+    } else {
+        // not found, forbidden, or redirected too many times
+        Ok(Some(StoredRules::AllowAll))
+    }
+}
+
+/// How long to keep a robots.txt served with these headers
+fn ttl(headers: &HeaderMap, now: SystemTime) -> time::Duration {
+    let cache_control = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|d| d.trim().to_lowercase())
+        .collect::<Vec<_>>();
+
+    let ttl = if cache_control
+        .iter()
+        .any(|d| d == "no-store" || d == "no-cache")
+    {
+        Some(time::Duration::ZERO)
+    } else if let Some(max_age) = cache_control.iter().find_map(|d| {
+        d.strip_prefix("max-age=")?
+            .trim_matches('"')
+            .parse::<i64>()
+            .ok()
+    }) {
+        Some(time::Duration::seconds(max_age))
+    } else {
+        headers
+            .get(EXPIRES)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok())
+            .map(|expires| match expires.duration_since(now) {
+                Ok(d) => time::Duration::seconds(d.as_secs() as i64),
+                Err(_) => time::Duration::ZERO,
+            })
+    };
+
+    ttl.unwrap_or(DEFAULT_TTL).clamp(MIN_TTL, DEFAULT_TTL)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use reqwest::header::HeaderMap;
+    use reqwest::header::HeaderValue;
+    use reqwest::header::CACHE_CONTROL;
+    use reqwest::header::EXPIRES;
+    use reqwest::StatusCode;
+    use url::Url;
+
+    use crate::crawler::robots_cache::robots_url;
+    use crate::crawler::robots_cache::rules_for_status;
+    use crate::crawler::robots_cache::ttl;
+    use crate::crawler::robots_cache::RobotsCache;
+    use crate::crawler::robots_cache::StoredRobots;
+    use crate::crawler::robots_cache::StoredRules;
+    use crate::crawler::robots_cache::DEFAULT_TTL;
+    use crate::crawler::robots_cache::ERROR_TTL;
+    use crate::crawler::robots_cache::MIN_TTL;
+    use crate::error::Error;
+
+    #[test]
+    fn test_robots_url() {
+        let url = Url::parse("http://user:pw@localhost:8099/a/b?c=d#e").unwrap();
+        assert_eq!(
+            robots_url(&url).unwrap().as_str(),
+            "http://localhost:8099/robots.txt"
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(rules_for_status(StatusCode::OK).unwrap(), None);
+        assert_eq!(
+            rules_for_status(StatusCode::NOT_FOUND).unwrap(),
+            Some(StoredRules::AllowAll)
+        );
+        assert!(matches!(
+            rules_for_status(StatusCode::SERVICE_UNAVAILABLE),
+            Err(Error::Request(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+        assert!(matches!(
+            rules_for_status(StatusCode::TOO_MANY_REQUESTS),
+            Err(Error::Request(StatusCode::TOO_MANY_REQUESTS))
+        ));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let headers = |name, value| {
+            let mut headers = HeaderMap::new();
+            headers.insert(name, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert_eq!(ttl(&HeaderMap::new(), now), DEFAULT_TTL);
+        assert_eq!(
+            ttl(&headers(CACHE_CONTROL, "public, max-age=3600"), now),
+            time::Duration::hours(1)
+        );
+        assert_eq!(
+            ttl(&headers(CACHE_CONTROL, "max-age=999999"), now),
+            DEFAULT_TTL
+        );
+        assert_eq!(ttl(&headers(CACHE_CONTROL, "no-cache"), now), MIN_TTL);
+        // 1_700_000_000 is Tue, 14 Nov 2023 22:13:20 GMT
+        assert_eq!(
+            ttl(&headers(EXPIRES, "Tue, 14 Nov 2023 23:13:20 GMT"), now),
+            time::Duration::hours(1)
+        );
+        assert_eq!(ttl(&headers(EXPIRES, "0"), now), DEFAULT_TTL);
+    }
+
+    #[test]
+    fn test_cache() {
+        let cache = RobotsCache {
+            memory: Mutex::new(HashMap::new()),
+            failed: Mutex::new(HashMap::new()),
+            db: Some(sled::Config::new().temporary(true).open().unwrap()),
+        };
+        let now = time::OffsetDateTime::now_utc();
+        let key = "https://example.com";
+        assert!(cache.lookup(key, now).unwrap().is_none());
+
+        let stored = StoredRobots {
+            expires: now + time::Duration::hours(1),
+            rules: StoredRules::Rules {
+                body: "User-agent: *\nDisallow: /private/\n".to_string(),
+            },
+        };
+        cache.insert(key, &stored).unwrap();
+
+        // still there once it has dropped out of memory
+        cache.memory.lock().unwrap().clear();
+        let cached = cache.lookup(key, now).unwrap().unwrap();
+        assert!(cached.allows(&Url::parse("https://example.com/public/").unwrap()));
+        assert!(!cached.allows(&Url::parse("https://example.com/private/a").unwrap()));
+
+        assert!(cache
+            .lookup(key, now + time::Duration::hours(2))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_cache_failure() {
+        let cache = RobotsCache {
+            memory: Mutex::new(HashMap::new()),
+            failed: Mutex::new(HashMap::new()),
+            db: Some(sled::Config::new().temporary(true).open().unwrap()),
+        };
+        let now = time::OffsetDateTime::now_utc();
+        let key = "https://example.com";
+        cache.insert_failure(key, now + ERROR_TTL);
+
+        assert!(matches!(
+            cache.lookup(key, now),
+            Err(Error::RobotsUnavailable(_))
+        ));
+        // nothing was written to disk
+        assert!(cache.db.as_ref().unwrap().is_empty());
+        // once it is time to try again there is nothing cached, so it is fetched
+        assert!(cache.lookup(key, now + ERROR_TTL).unwrap().is_none());
+    }
+}
@@ -0,0 +1,144 @@
+//! Page level indexing rules, from `<meta name="robots">` tags and the `X-Robots-Tag` header.
+//!
+//! Unlike robots.txt these are found on the page itself, so they can only be checked once it has been fetched. Rules
+//! for other crawlers are ignored, as are rules like `max-snippet` that don't apply to us.
+
+use reqwest::header::HeaderMap;
+
+use crate::crawler::web_client::USER_AGENT;
+
+pub const ROBOTS_TAG_HEADER: &str = "x-robots-tag";
+
+/// Name we answer to in meta tags and headers, as well as the full user agent
+pub const ROBOTS_NAME: &str = "ceridwen";
+
+/// Rules in the header that have a value after a colon, so the colon doesn't mean the rule is for one crawler
+const RULES_WITH_VALUES: [&str; 4] = [
+    "unavailable_after",
+    "max-snippet",
+    "max-image-preview",
+    "max-video-preview",
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RobotsDirectives {
+    /// the page must not be indexed
+    pub noindex: bool,
+    /// links on the page must not be followed or counted
+    pub nofollow: bool,
+}
+
+impl RobotsDirectives {
+    /// Add the rules from the content of a robots meta tag, or a header value meant for us, like
+    /// `noindex, nofollow`.
+    pub fn add_rules(&mut self, rules: &str) {
+        for rule in rules.split(',').map(|r| r.trim().to_lowercase()) {
+            match rule.as_str() {
+                "noindex" => self.noindex = true,
+                "nofollow" => self.nofollow = true,
+                "none" => {
+                    self.noindex = true;
+                    self.nofollow = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Add the rules from every `X-Robots-Tag` header that applies to us. Rules can be for every crawler, or start
+    /// with the name of the crawler they are for, like `otherbot: noindex`.
+    pub fn add_headers(&mut self, headers: &HeaderMap) {
+        for value in headers.get_all(ROBOTS_TAG_HEADER).iter() {
+            let value = match value.to_str() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            match value.split_once(':') {
+                Some((name, rules)) if is_crawler_name(name) => {
+                    if is_us(name) {
+                        self.add_rules(rules);
+                    }
+                }
+                _ => self.add_rules(value),
+            }
+        }
+    }
+}
+
+/// True if the text before the first colon in a header names a crawler. Anything else, like the `noindex, ` in
+/// `noindex, unavailable_after: 25 Jun 2010`, means the colon belongs to a rule with a value.
+fn is_crawler_name(name: &str) -> bool {
+    let name = name.trim();
+    !name.is_empty()
+        && !name.contains(|c: char| c == ',' || c.is_whitespace())
+        && !RULES_WITH_VALUES.contains(&name.to_lowercase().as_str())
+}
+
+/// True if a meta tag name or header crawler name is talking to us
+pub fn is_us(name: &str) -> bool {
+    let name = name.trim();
+    name.eq_ignore_ascii_case(ROBOTS_NAME) || name.eq_ignore_ascii_case(USER_AGENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderMap;
+    use reqwest::header::HeaderValue;
+
+    use crate::crawler::robots_tags::RobotsDirectives;
+    use crate::crawler::robots_tags::ROBOTS_TAG_HEADER;
+
+    #[test]
+    fn test_rules() {
+        let mut directives = RobotsDirectives::default();
+        directives.add_rules("index, follow, max-snippet:50");
+        assert_eq!(directives, RobotsDirectives::default());
+
+        directives.add_rules(" NOINDEX ");
+        assert!(directives.noindex && !directives.nofollow);
+
+        let mut directives = RobotsDirectives::default();
+        directives.add_rules("none");
+        assert!(directives.noindex && directives.nofollow);
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            ROBOTS_TAG_HEADER,
+            HeaderValue::from_static("otherbot: noindex"),
+        );
+        headers.append(
+            ROBOTS_TAG_HEADER,
+            HeaderValue::from_static("unavailable_after: 25 Jun 2010 15:00:00 PST"),
+        );
+        let mut directives = RobotsDirectives::default();
+        directives.add_headers(&headers);
+        assert_eq!(directives, RobotsDirectives::default());
+
+        headers.append(
+            ROBOTS_TAG_HEADER,
+            HeaderValue::from_static("Ceridwen: nofollow"),
+        );
+        headers.append(ROBOTS_TAG_HEADER, HeaderValue::from_static("noindex"));
+        directives.add_headers(&headers);
+        assert!(directives.noindex && directives.nofollow);
+    }
+
+    #[test]
+    fn test_header_rules_with_values() {
+        let header = |value| {
+            let mut headers = HeaderMap::new();
+            headers.append(ROBOTS_TAG_HEADER, HeaderValue::from_static(value));
+            let mut directives = RobotsDirectives::default();
+            directives.add_headers(&headers);
+            directives
+        };
+
+        assert!(header("noindex, unavailable_after: 25 Jun 2010 15:00:00 PST").noindex);
+        assert!(header("max-snippet: 20, NOINDEX").noindex);
+        assert!(header("ceridwen: noindex, unavailable_after: 25 Jun 2010").noindex);
+        assert!(!header("otherbot: noindex, unavailable_after: 25 Jun 2010").noindex);
+    }
+}
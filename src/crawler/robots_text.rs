@@ -1,64 +1,37 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::io::BufRead;
+use std::time::Duration;
 
 use crate::error::Error;
 use bytes::Bytes;
 use reqwest::Client;
 use url::Url;
 
-use crate::crawler::web_client;
+use crate::crawler::robots_cache;
 
 /// Returns a true result if the robots.txt file for the url provided allows us to process it.
 pub async fn check_robots_file(client: &Client, url: &Url) -> Result<bool, Error> {
-    let target_url = robots_url(url)?;
-
-    // TODO: cache the robots.txt file somewhere
-
-    let robots_result = web_client::get_url(client, &target_url).await;
-    // if the robots.txt file isn't found we can return that we are allowed to load the page
-    if robots_result.is_err()
-        && robots_result.as_ref().unwrap_err() == &Error::PageNotFound(target_url.to_string())
-    {
-        return Ok(true);
-    }
-
-    // Parse the file
-    let robots = Robots::parse_file(robots_result.unwrap())?;
-
-    // look for our user agent and url and see if it matches
-    let rule = robots.check_url(web_client::USER_AGENT, url);
-    match rule {
-        Some(RobotRule::Deny(_)) => Ok(false),
-        Some(RobotRule::Allow(_)) => Ok(true),
-        None => Ok(true),
-    }
-}
-
-fn robots_url(url: &Url) -> Result<Url, Error> {
-    match url.host_str() {
-        Some(host) => Ok(Url::parse(&format!(
-            "{}://{}/robots.txt",
-            url.scheme(),
-            host
-        ))?),
-        None => Err(Error::MissingHost(url.to_string())),
-    }
+    Ok(robots_cache::rules_for(client, url).await?.allows(url))
 }
 
 #[derive(Debug, Clone)]
-struct Robots {
-    // TODO: support for site map urls
+pub(super) struct Robots {
     entries: HashMap<String, Vec<RobotRule>>,
+    /// how long to wait between requests, for the user agents that say
+    crawl_delays: HashMap<String, Duration>,
+    /// sitemaps the site lists. These apply to everyone so aren't part of any group.
+    sitemaps: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-enum RobotRule {
+pub(super) enum RobotRule {
     Allow(String),
     Deny(String),
 }
 
 impl Robots {
-    fn parse_file(body: Bytes) -> Result<Self, Error> {
+    pub(super) fn parse_file(body: Bytes) -> Result<Self, Error> {
         #[derive(Debug, PartialEq, Eq)]
         enum ParseState {
             UserAgents,
@@ -67,10 +40,13 @@ impl Robots {
 
         let mut result = Robots {
             entries: HashMap::new(),
+            crawl_delays: HashMap::new(),
+            sitemaps: Vec::new(),
         };
 
         let mut user_agent_buffer: Vec<String> = Vec::new();
         let mut rules_buffer: Vec<RobotRule> = Vec::new();
+        let mut crawl_delay: Option<Duration> = None;
         let mut parse_state = ParseState::UserAgents;
 
         for line_result in body.lines() {
@@ -79,64 +55,91 @@ impl Robots {
             }
 
             let line = line_result.unwrap();
-            let trimmed = line.trim();
-            // skip blank lines and comments
-            if trimmed.is_empty() || trimmed.starts_with('#') {
-                continue;
-            }
+            // comments can start part way through a line
+            let line = match line.split_once('#') {
+                Some((before, _)) => before,
+                None => line.as_str(),
+            };
+            // field names are case insensitive and can have any amount of space around the colon, or none
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
 
-            if trimmed.len() > 12 && trimmed[0..12].to_lowercase() == "user-agent: " {
-                // this is the start of a new block of useragents so make sure the rules from the previous block
-                // are added to the result
-                if parse_state == ParseState::Rules {
-                    for ua in user_agent_buffer.iter() {
-                        result
-                            .entries
-                            .entry(ua.to_string())
-                            .or_default()
-                            .append(&mut rules_buffer.clone())
+            match field.as_str() {
+                "user-agent" => {
+                    // this is the start of a new block of useragents so make sure the rules from the previous block
+                    // are added to the result
+                    if parse_state == ParseState::Rules {
+                        result.add_group(&user_agent_buffer, &rules_buffer, crawl_delay);
+                        user_agent_buffer.clear();
+                        rules_buffer.clear();
+                        crawl_delay = None;
+                        parse_state = ParseState::UserAgents;
                     }
-                    user_agent_buffer.clear();
-                    rules_buffer.clear();
-                    parse_state = ParseState::UserAgents;
-                }
 
-                // Now process the user agent
-                user_agent_buffer.push(trimmed[12..].to_string());
-            } else if trimmed.len() > 10 && trimmed[..10].to_lowercase() == "disallow: " {
-                if parse_state == ParseState::UserAgents {
-                    parse_state = ParseState::Rules;
+                    // Now process the user agent
+                    user_agent_buffer.push(value.to_lowercase());
                 }
-                rules_buffer.push(RobotRule::Deny(trimmed[10..].to_string()))
-            } else if trimmed.len() > 7 && trimmed[..7].to_lowercase() == "allow: " {
-                if parse_state == ParseState::UserAgents {
-                    parse_state = ParseState::Rules;
+                "allow" | "disallow" => {
+                    if parse_state == ParseState::UserAgents {
+                        parse_state = ParseState::Rules;
+                    }
+                    // an empty rule doesn't match anything
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let path = normalise_path(value);
+                    rules_buffer.push(if field == "allow" {
+                        RobotRule::Allow(path)
+                    } else {
+                        RobotRule::Deny(path)
+                    })
+                }
+                "crawl-delay" => {
+                    if parse_state == ParseState::UserAgents {
+                        parse_state = ParseState::Rules;
+                    }
+                    // the delay is in seconds, and can have a fraction. Anything we can't make sense of is ignored.
+                    crawl_delay = value
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .or(crawl_delay);
                 }
-                rules_buffer.push(RobotRule::Allow(trimmed[7..].to_string()))
+                "sitemap" if !value.is_empty() => result.sitemaps.push(value.to_string()),
+                // other fields don't end a group
+                _ => {}
             }
         }
 
         // Add the last group to the results
-        for ua in user_agent_buffer.iter() {
-            result
-                .entries
+        result.add_group(&user_agent_buffer, &rules_buffer, crawl_delay);
+
+        Ok(result)
+    }
+
+    fn add_group(
+        &mut self,
+        user_agents: &[String],
+        rules: &[RobotRule],
+        crawl_delay: Option<Duration>,
+    ) {
+        for ua in user_agents.iter() {
+            self.entries
                 .entry(ua.to_string())
                 .or_default()
-                .append(&mut rules_buffer.clone())
-        }
-
-        // if there is more than one rule for a url match, the last one should win, so reverse the list.
-        for (_, v) in result.entries.iter_mut() {
-            v.reverse();
+                .extend_from_slice(rules);
+            if let Some(delay) = crawl_delay {
+                self.crawl_delays.insert(ua.to_string(), delay);
+            }
         }
-
-        Ok(result)
     }
 
     pub fn get_rules(&self, name: &str) -> Vec<RobotRule> {
         // get rules mentioning this entry by name
         // if there are no rules matching by name check for wild card rules
-        match self.entries.get(name) {
+        match self.entries.get(&name.to_lowercase()) {
             Some(v) => v.to_vec(),
             None => match self.entries.get("*") {
                 Some(v) => v.to_vec(),
@@ -145,35 +148,152 @@ impl Robots {
         }
     }
 
+    /// Urls of the sitemaps the site lists, as they were written
+    pub fn sitemaps(&self) -> &[String] {
+        &self.sitemaps
+    }
+
+    /// How long the site asks name to wait between requests, if it says. Comes from the same group of rules
+    /// get_rules would use.
+    pub fn crawl_delay(&self, name: &str) -> Option<Duration> {
+        let name = name.to_lowercase();
+        if self.entries.contains_key(&name) {
+            self.crawl_delays.get(&name).copied()
+        } else {
+            self.crawl_delays.get("*").copied()
+        }
+    }
+
+    /// The rule that decides whether user_agent may fetch url, if any does. When more than one rule matches the
+    /// longest wins, and if an allow and a deny rule are just as long the allow rule wins.
     pub fn check_url(&self, user_agent: &str, url: &Url) -> Option<RobotRule> {
-        let rules = self.get_rules(user_agent);
-        if rules.is_empty() {
+        let mut file_path = url.path().to_string();
+        // the robots.txt file itself is always allowed
+        if file_path == "/robots.txt" {
             return None;
         }
+        if let Some(query) = url.query() {
+            file_path.push('?');
+            file_path.push_str(query);
+        }
+        let file_path = normalise_path(&file_path);
 
-        let file_path = url.path();
-
-        // TODO: longest path matching?
-        for rule in rules.iter() {
-            // TODO: allow wild card matching rules in here. Regex?
-            let matches = match rule {
-                RobotRule::Allow(prefix) => file_path.starts_with(prefix),
-                RobotRule::Deny(prefix) => file_path.starts_with(prefix),
+        let mut best: Option<RobotRule> = None;
+        for rule in self.get_rules(user_agent) {
+            if !path_matches(rule.path(), &file_path) {
+                continue;
+            }
+            let better = match &best {
+                None => true,
+                Some(current) => match rule.path().len().cmp(&current.path().len()) {
+                    Ordering::Greater => true,
+                    Ordering::Equal => matches!(rule, RobotRule::Allow(_)),
+                    Ordering::Less => false,
+                },
             };
+            if better {
+                best = Some(rule);
+            }
+        }
+        best
+    }
+}
+
+impl RobotRule {
+    fn path(&self) -> &str {
+        match self {
+            RobotRule::Allow(path) => path,
+            RobotRule::Deny(path) => path,
+        }
+    }
+}
 
-            if matches {
-                return Some(rule.clone());
+/// Percent encode a path the same way whether it came from a robots.txt file or a url, so they can be compared.
+/// Characters that don't need encoding are decoded, everything else that isn't plain ascii is encoded, and escapes
+/// are upper cased.
+fn normalise_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut result = String::with_capacity(path.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex = &path[i + 1..i + 3];
+            let decoded = u8::from_str_radix(hex, 16).unwrap_or_default();
+            if decoded.is_ascii_alphanumeric() || b"-._~".contains(&decoded) {
+                result.push(decoded as char);
+            } else {
+                result.push('%');
+                result.push_str(&hex.to_uppercase());
             }
+            i += 3;
+            continue;
+        }
+        if b.is_ascii() && !b.is_ascii_control() && b != b' ' {
+            result.push(b as char);
+        } else {
+            result.push_str(&format!("%{b:02X}"));
         }
+        i += 1;
+    }
+    result
+}
 
-        None
+/// True if a rule's path matches the start of a url path. `*` in the rule matches any run of characters and a `$` at
+/// the end means the url path must end there too.
+fn path_matches(rule: &str, path: &str) -> bool {
+    let (rule, anchored) = match rule.strip_suffix('$') {
+        Some(rule) => (rule, true),
+        None => (rule, false),
+    };
+    let rule = rule.as_bytes();
+    let path = path.as_bytes();
+
+    // positions in the path the rule could have got to so far
+    let mut positions = vec![0];
+    for (i, &c) in rule.iter().enumerate() {
+        if c == b'*' {
+            // a run of stars is no different to one
+            if i > 0 && rule[i - 1] == b'*' {
+                continue;
+            }
+            let first = match positions.first() {
+                Some(p) => *p,
+                None => return false,
+            };
+            positions = (first..=path.len()).collect();
+        } else {
+            positions = positions
+                .into_iter()
+                .filter(|&p| p < path.len() && path[p] == c)
+                .map(|p| p + 1)
+                .collect();
+            if positions.is_empty() {
+                return false;
+            }
+        }
+    }
+
+    if anchored {
+        positions.contains(&path.len())
+    } else {
+        !positions.is_empty()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use bytes::Bytes;
+    use url::Url;
 
+    use crate::crawler::robots_text::normalise_path;
+    use crate::crawler::robots_text::path_matches;
     use crate::crawler::robots_text::RobotRule;
     use crate::crawler::robots_text::Robots;
 
@@ -193,7 +313,7 @@ Disallow: /bullpen/
         let input_bytes = Bytes::from(input);
 
         let result = Robots::parse_file(input_bytes);
-        assert!(!result.is_err(), "Parsing input should not have failed");
+        assert!(result.is_ok(), "Parsing input should not have failed");
 
         let robots_result = result.unwrap();
         println!("Result: {:?}", robots_result);
@@ -224,4 +344,99 @@ Disallow: /bullpen/
             "wildcard rule not as expected"
         )
     }
+
+    #[test]
+    fn test_robots_parsing_spacing() {
+        let input = "USER-AGENT:Ceridwen-Crawler # us
+disallow :/a   # comment
+Allow:
+Sitemap: https://example.com/sitemap.xml
+Crawl-delay: 2.5
+Disallow:\t/b
+
+User-agent: *
+Crawl-delay: soon
+";
+        let robots = Robots::parse_file(Bytes::from(input)).unwrap();
+        assert_eq!(
+            robots.get_rules("ceridwen-crawler"),
+            [
+                RobotRule::Deny("/a".to_string()),
+                RobotRule::Deny("/b".to_string())
+            ]
+        );
+        assert_eq!(
+            robots.crawl_delay("Ceridwen-Crawler"),
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(robots.crawl_delay("otherbot"), None);
+        assert_eq!(robots.sitemaps(), ["https://example.com/sitemap.xml"]);
+    }
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("/fish", "/fish.html"));
+        assert!(!path_matches("/fish", "/Fish.asp"));
+        assert!(path_matches("/*.php", "/folder/filename.php?parameters"));
+        assert!(!path_matches("/*.php", "/windows.PHP"));
+        assert!(path_matches("/*.php$", "/filename.php"));
+        assert!(!path_matches("/*.php$", "/filename.php?parameters"));
+        assert!(path_matches(
+            "/fish*.php",
+            "/fishheads/catfish.php?parameters"
+        ));
+        assert!(path_matches("/a**b$", "/ab"));
+        assert!(path_matches("*", "/"));
+    }
+
+    #[test]
+    fn test_normalise_path() {
+        assert_eq!(normalise_path("/%7efoo/%2fbar"), "/~foo/%2Fbar");
+        assert_eq!(normalise_path("/caf\u{e9}"), "/caf%C3%A9");
+        assert_eq!(normalise_path("/100%"), "/100%");
+    }
+
+    #[test]
+    fn test_check_url() {
+        let input = "User-agent: *
+Disallow: /
+Allow: /public
+Disallow: /public/secret
+Allow: /page
+Disallow: /*.htm
+Disallow: /search?q=
+Disallow: /caf\u{e9}
+
+User-agent: otherbot
+Allow: /
+";
+        let robots = Robots::parse_file(Bytes::from(input)).unwrap();
+        let check = |path: &str| {
+            let url = Url::parse(&format!("https://example.com{path}")).unwrap();
+            robots.check_url("ceridwen-crawler", &url)
+        };
+
+        assert!(matches!(check("/other"), Some(RobotRule::Deny(_))));
+        assert!(matches!(check("/public/a"), Some(RobotRule::Allow(_))));
+        assert!(matches!(
+            check("/public/secret/a"),
+            Some(RobotRule::Deny(_))
+        ));
+        // allow wins when both rules are as long
+        assert!(matches!(check("/page"), Some(RobotRule::Allow(_))));
+        assert!(matches!(check("/pages.htm"), Some(RobotRule::Deny(_))));
+        assert!(matches!(check("/search?q=otter"), Some(RobotRule::Deny(_))));
+        assert!(matches!(
+            check("/public?q=otter"),
+            Some(RobotRule::Allow(_))
+        ));
+        assert!(matches!(check("/caf%C3%A9"), Some(RobotRule::Deny(_))));
+        assert_eq!(check("/robots.txt"), None);
+
+        let url = Url::parse("https://example.com/other").unwrap();
+        assert!(matches!(
+            robots.check_url("OtherBot", &url),
+            Some(RobotRule::Allow(_))
+        ));
+    }
 }
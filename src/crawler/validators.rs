@@ -0,0 +1,109 @@
+//! Remembers the `ETag` and `Last-Modified` headers pages and feeds were served with, so the next request for them
+//! can ask the server to only send them again if they have changed.
+//!
+//! Kept in the collection the pages are indexed in, keyed by url, so they are forgotten when the pages are deleted.
+//! Callers save them once they have finished with what they fetched, so a crawl that fails part way through doesn't
+//! leave us thinking we have something we don't.
+
+use reqwest::header::HeaderMap;
+use reqwest::header::ETAG;
+use reqwest::header::IF_MODIFIED_SINCE;
+use reqwest::header::IF_NONE_MATCH;
+use reqwest::header::LAST_MODIFIED;
+use serde::Deserialize;
+use serde::Serialize;
+use url::Url;
+
+use crate::error::Error;
+use crate::index_sled::Index;
+
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// The validators in a response's headers, if it had any
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        if validators == Validators::default() {
+            None
+        } else {
+            Some(validators)
+        }
+    }
+
+    /// Headers to send to only get a response body if it has changed
+    pub fn request_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = self.etag.as_ref().and_then(|v| v.parse().ok()) {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        if let Some(modified) = self.last_modified.as_ref().and_then(|v| v.parse().ok()) {
+            headers.insert(IF_MODIFIED_SINCE, modified);
+        }
+        headers
+    }
+}
+
+/// The validators we last saw for url
+pub fn load(index: &Index, url: &Url) -> Result<Option<Validators>, Error> {
+    match index.validators(url.as_str())? {
+        Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+        None => Ok(None),
+    }
+}
+
+/// Remember the validators url was served with when fetched by ingester. Any we had are forgotten if it didn't come
+/// with any.
+pub fn save(index: &Index, url: &Url, ingester: &str, headers: &HeaderMap) -> Result<(), Error> {
+    let value = match Validators::from_headers(headers) {
+        Some(validators) => Some(serde_json::to_vec(&validators)?),
+        None => None,
+    };
+    index.save_validators(url.as_str(), ingester, value.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderMap;
+    use reqwest::header::HeaderValue;
+    use reqwest::header::ETAG;
+    use reqwest::header::IF_MODIFIED_SINCE;
+    use reqwest::header::IF_NONE_MATCH;
+    use reqwest::header::LAST_MODIFIED;
+
+    use crate::crawler::validators::Validators;
+
+    #[test]
+    fn test_request_headers() {
+        assert_eq!(Validators::from_headers(&HeaderMap::new()), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, HeaderValue::from_static("W/\"abc\""));
+        headers.insert(
+            LAST_MODIFIED,
+            HeaderValue::from_static("Tue, 14 Nov 2023 22:13:20 GMT"),
+        );
+        let validators = Validators::from_headers(&headers).unwrap();
+
+        let request = validators.request_headers();
+        assert_eq!(request.get(IF_NONE_MATCH).unwrap(), "W/\"abc\"");
+        assert_eq!(
+            request.get(IF_MODIFIED_SINCE).unwrap(),
+            "Tue, 14 Nov 2023 22:13:20 GMT"
+        );
+    }
+}
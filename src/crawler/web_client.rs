@@ -1,15 +1,25 @@
 use std::path::Path;
 use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
 
 use crate::config::Config;
+use crate::crawler::politeness;
+use crate::crawler::politeness::Turn;
+use crate::crawler::retry;
+use crate::crawler::robots_cache;
+use crate::crawler::validators::Validators;
 use crate::error::Error;
 use crate::utils::percentage::percentage;
 
 use bytes::Bytes;
+use bytes::BytesMut;
 use humansize::{format_size, DECIMAL};
 use log::debug;
 use log::warn;
+use reqwest::header::HeaderMap;
 use reqwest::Client;
+use reqwest::Response;
 use reqwest::StatusCode;
 use tokio::fs;
 use tokio::fs::OpenOptions;
@@ -25,11 +35,97 @@ pub fn get_client(_config: &Config) -> Result<Client, Error> {
         .build()?)
 }
 
+/// A page we have fetched, along with the headers it came with
+pub struct FetchedPage {
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
 pub async fn get(client: &Client, url: &str) -> Result<Bytes, Error> {
-    debug!("Making request for {}", url);
-    let start_time = time::Instant::now();
+    Ok(fetch(client, url, HeaderMap::new(), None).await?.body)
+}
+
+/// Fetch a page when we need the response headers as well as the body.
+pub async fn get_page(client: &Client, url: &Url) -> Result<FetchedPage, Error> {
+    fetch(client, url.as_str(), HeaderMap::new(), None).await
+}
+
+/// Fetch a page only if it has changed since it was served with validators. None if the server says it hasn't. Save
+/// the validators from the response once you are done with it.
+pub async fn get_page_if_changed(
+    client: &Client,
+    url: &Url,
+    validators: Option<&Validators>,
+) -> Result<Option<FetchedPage>, Error> {
+    let headers = match validators {
+        Some(v) => v.request_headers(),
+        None => HeaderMap::new(),
+    };
+    match fetch(client, url.as_str(), headers, None).await {
+        Ok(page) => Ok(Some(page)),
+        Err(Error::Request(StatusCode::NOT_MODIFIED)) => {
+            debug!("{url} has not changed");
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Make a request, trying again if it fails in a way that might work next time. Returns the turn the request was made
+/// in along with the response, hold on to it until the body has been read. crawl_delay is the `Crawl-delay` from the
+/// site's robots.txt.
+pub(super) async fn send(
+    client: &Client,
+    url: &Url,
+    headers: HeaderMap,
+    crawl_delay: Option<Duration>,
+) -> Result<(Turn, Response), Error> {
+    let policy = retry::policy();
+    let mut failures = 0;
+    loop {
+        let turn = politeness::wait_turn(url, crawl_delay).await?;
+        debug!("Making request for {}", url);
+        let request = client.get(url.as_str()).headers(headers.clone()).build()?;
+        let (error, retry_after) = match client.execute(request).await {
+            Ok(response) if retry::is_retryable(response.status()) => {
+                let retry_after = retry::retry_after(response.headers(), SystemTime::now());
+                (Error::Request(response.status()), retry_after)
+            }
+            Ok(response) => {
+                turn.succeeded();
+                return Ok((turn, response));
+            }
+            Err(e) if retry::is_retryable_error(&e) => (e.into(), None),
+            Err(e) => return Err(e.into()),
+        };
+        failures += 1;
+        match policy.delay(failures, retry_after) {
+            Some(delay) => {
+                // let other requests to the host go while we wait
+                drop(turn);
+                warn!("Request for {url} failed, trying again in {delay:?}: {error}");
+                tokio::time::sleep(delay).await;
+            }
+            None => {
+                // only count the request against the host once we have given up on it
+                turn.failed();
+                return Err(error);
+            }
+        }
+    }
+}
 
-    let response = client.execute(client.get(url).build()?).await?;
+/// Make a request and read the body. If max_size is set, give up on bodies bigger than that rather than reading them.
+async fn fetch(
+    client: &Client,
+    url: &str,
+    headers: HeaderMap,
+    max_size: Option<u64>,
+) -> Result<FetchedPage, Error> {
+    let start_time = Instant::now();
+    let url = Url::parse(url)?;
+    let crawl_delay = robots_cache::rules_for(client, &url).await?.crawl_delay();
+    let (_turn, mut response) = send(client, &url, headers, crawl_delay).await?;
 
     debug!("Got {} back from {}", response.status(), url);
     if response.status() == StatusCode::NOT_FOUND {
@@ -38,21 +134,53 @@ pub async fn get(client: &Client, url: &str) -> Result<Bytes, Error> {
         return Err(Error::Request(response.status()));
     }
 
-    let file_bytes = response.bytes().await?;
+    let headers = response.headers().clone();
+    let file_bytes = match max_size {
+        None => response.bytes().await?,
+        Some(max_size) => {
+            if response
+                .content_length()
+                .is_some_and(|length| length > max_size)
+            {
+                return Err(Error::ResponseTooLarge(url.to_string(), max_size));
+            }
+            // the content length can be missing or wrong, so keep count as well
+            let mut body = BytesMut::new();
+            while let Some(chunk) = response.chunk().await? {
+                if (body.len() + chunk.len()) as u64 > max_size {
+                    return Err(Error::ResponseTooLarge(url.to_string(), max_size));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            body.freeze()
+        }
+    };
     debug!(
-        "Response size: {} for {} in {}",
+        "Response size: {} for {} in {:?}",
         file_bytes.len(),
         url,
         start_time.elapsed()
     );
 
-    Ok(file_bytes)
+    Ok(FetchedPage {
+        headers,
+        body: file_bytes,
+    })
 }
 
 pub async fn get_url(client: &Client, url: &Url) -> Result<Bytes, Error> {
     get(client, url.as_str()).await
 }
 
+/// Fetch a url, failing with [`Error::ResponseTooLarge`] if the body is more than max_size bytes.
+pub async fn get_url_limited(client: &Client, url: &Url, max_size: u64) -> Result<Bytes, Error> {
+    Ok(
+        fetch(client, url.as_str(), HeaderMap::new(), Some(max_size))
+            .await?
+            .body,
+    )
+}
+
 pub async fn get_to_file(client: &Client, url: &str, target_path: &Path) -> Result<(), Error> {
     debug!("Attempting to download {url} to {target_path:?}");
     fs::create_dir_all(target_path.parent().unwrap()).await?;
@@ -63,9 +191,13 @@ pub async fn get_to_file(client: &Client, url: &str, target_path: &Path) -> Resu
         .open(target_path)
         .await?;
 
-    let download_start = time::Instant::now();
+    let download_start = Instant::now();
 
-    let mut response = client.get(url).send().await?;
+    let parsed_url = Url::parse(url)?;
+    let crawl_delay = robots_cache::rules_for(client, &parsed_url)
+        .await?
+        .crawl_delay();
+    let (_turn, mut response) = send(client, &parsed_url, HeaderMap::new(), crawl_delay).await?;
 
     debug!("Got {} back from {}", response.status(), url);
     if response.status() == StatusCode::NOT_FOUND {
@@ -112,7 +244,7 @@ pub async fn get_to_file(client: &Client, url: &str, target_path: &Path) -> Resu
     }
 
     let download_duration = download_start.elapsed();
-    debug!("Download took: {}", download_duration);
+    debug!("Download took: {:?}", download_duration);
 
     Ok(())
 }
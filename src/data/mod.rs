@@ -2,41 +2,247 @@ use serde::Deserialize;
 use serde::Serialize;
 use sled::IVec;
 
+use crate::error::Error;
+use crate::utils::varint;
+
 /// A type that describes a page on the internet that we want to index.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Page {
     pub url: url::Url,
     pub title: String,
     pub content: String,
+    /// name of the ingester that found this page
+    pub ingester: String,
+    /// pages this page links to
+    #[serde(default)]
+    pub links: Vec<url::Url>,
 }
 
+/// What we store in the index for each page.
+/// New fields must be given a `#[serde(default)]` so records written before they existed can still be read, and must
+/// be added to the end of the binary record format.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SearchResult {
     pub url: String,
     pub title: String,
     pub description: String,
     pub last_index: time::OffsetDateTime,
+    #[serde(default)]
+    pub ingester: String,
+    /// when the page was first added to the index, unlike last_index this doesn't change when it is indexed again.
+    /// None for pages added before we kept track.
+    #[serde(default)]
+    pub first_index: Option<time::OffsetDateTime>,
+}
+
+impl SearchResult {
+    /// When the page was first added to the index, as near as we know
+    pub fn first_indexed(&self) -> time::OffsetDateTime {
+        self.first_index.unwrap_or(self.last_index)
+    }
+}
+
+/// A page found by a search, along with what is needed to find it in the index again.
+#[derive(Debug, Serialize, Clone)]
+pub struct SearchHit {
+    pub id: u64,
+    pub collection: String,
+    #[serde(flatten)]
+    pub page: SearchResult,
+}
+
+impl SearchHit {
+    /// A hit for a page at url with nothing else of note about it, for tests
+    #[cfg(test)]
+    pub(crate) fn example(url: &str, last_index: time::OffsetDateTime) -> Self {
+        SearchHit {
+            id: 0,
+            collection: "default".to_string(),
+            page: SearchResult {
+                url: url.to_string(),
+                title: url.to_string(),
+                description: String::new(),
+                last_index,
+                ingester: String::new(),
+                first_index: None,
+            },
+        }
+    }
 }
 
 impl From<&Page> for SearchResult {
     fn from(value: &Page) -> Self {
+        let now = time::OffsetDateTime::now_utc();
         SearchResult {
             url: value.url.to_string(),
             title: value.title.clone(),
             description: value.content.chars().take(250).collect(),
-            last_index: time::OffsetDateTime::now_utc(),
+            last_index: now,
+            ingester: value.ingester.clone(),
+            first_index: Some(now),
         }
     }
 }
 
-impl From<IVec> for SearchResult {
-    fn from(value: IVec) -> Self {
-        serde_json::from_slice(value.as_ref()).unwrap()
+/// First byte of a binary encoded SearchResult. Older records were stored as json so start with `{`
+const RECORD_FORMAT: u8 = 1;
+
+impl TryFrom<IVec> for SearchResult {
+    type Error = Error;
+
+    fn try_from(value: IVec) -> Result<Self, Self::Error> {
+        match value.first() {
+            Some(&RECORD_FORMAT) => decode_record(&value[1..]).ok_or(Error::BadIndexRecord),
+            Some(b'{') => Ok(serde_json::from_slice(value.as_ref())?),
+            _ => Err(Error::BadIndexRecord),
+        }
     }
 }
 
 impl From<SearchResult> for IVec {
     fn from(val: SearchResult) -> Self {
-        serde_json::to_string(&val).unwrap().as_bytes().into()
+        let mut buffer = vec![RECORD_FORMAT];
+        varint::write_bytes(&mut buffer, val.url.as_bytes());
+        varint::write_bytes(&mut buffer, val.title.as_bytes());
+        varint::write_bytes(&mut buffer, val.description.as_bytes());
+        write_time(&mut buffer, val.last_index);
+        varint::write_bytes(&mut buffer, val.ingester.as_bytes());
+        match val.first_index {
+            Some(first_index) => {
+                varint::write_u64(&mut buffer, 1);
+                write_time(&mut buffer, first_index);
+            }
+            None => varint::write_u64(&mut buffer, 0),
+        }
+        buffer.into()
+    }
+}
+
+fn decode_record(mut input: &[u8]) -> Option<SearchResult> {
+    let input = &mut input;
+    let url = read_string(input)?;
+    let title = read_string(input)?;
+    let description = read_string(input)?;
+    let last_index = read_time(input)?;
+    let ingester = read_string(input)?;
+    // records written before we kept the first index time end here
+    let first_index = if input.is_empty() {
+        None
+    } else {
+        match varint::read_u64(input)? {
+            0 => None,
+            _ => Some(read_time(input)?),
+        }
+    };
+
+    Some(SearchResult {
+        url,
+        title,
+        description,
+        last_index,
+        ingester,
+        first_index,
+    })
+}
+
+fn write_time(buffer: &mut Vec<u8>, time: time::OffsetDateTime) {
+    varint::write_i64(buffer, time.unix_timestamp());
+    varint::write_u64(buffer, time.nanosecond() as u64);
+}
+
+fn read_time(input: &mut &[u8]) -> Option<time::OffsetDateTime> {
+    let seconds = varint::read_i64(input)?;
+    let nanoseconds = varint::read_u64(input)?;
+    time::OffsetDateTime::from_unix_timestamp(seconds)
+        .ok()?
+        .replace_nanosecond(u32::try_from(nanoseconds).ok()?)
+        .ok()
+}
+
+fn read_string(input: &mut &[u8]) -> Option<String> {
+    String::from_utf8(varint::read_bytes(input)?.to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use sled::IVec;
+
+    use crate::data::SearchResult;
+    use crate::data::RECORD_FORMAT;
+    use crate::utils::varint;
+
+    fn example() -> SearchResult {
+        SearchResult {
+            url: "https://en.wikipedia.org/wiki/Ceridwen".to_string(),
+            title: "Ceridwen".to_string(),
+            description: "Ceridwen is an enchantress in Welsh medieval legend.".to_string(),
+            last_index: time::macros::datetime!(2024-02-02 09:03:51.123456789 UTC),
+            ingester: "wikipedia".to_string(),
+            first_index: Some(time::macros::datetime!(2023-05-06 07:08:09 UTC)),
+        }
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let encoded: IVec = example().into();
+        let decoded = SearchResult::try_from(encoded).unwrap();
+
+        let expected = example();
+        assert_eq!(decoded.url, expected.url);
+        assert_eq!(decoded.title, expected.title);
+        assert_eq!(decoded.description, expected.description);
+        assert_eq!(decoded.last_index, expected.last_index);
+        assert_eq!(decoded.ingester, expected.ingester);
+        assert_eq!(decoded.first_index, expected.first_index);
+    }
+
+    #[test]
+    fn test_reads_records_without_first_index() {
+        let mut old = example();
+        old.first_index = None;
+        let encoded: IVec = old.into();
+        // records from before we kept the time end straight after the ingester
+        let decoded = SearchResult::try_from(IVec::from(&encoded[..encoded.len() - 1])).unwrap();
+        assert_eq!(decoded.ingester, "wikipedia");
+        assert_eq!(decoded.first_index, None);
+        assert_eq!(decoded.first_indexed(), decoded.last_index);
+    }
+
+    #[test]
+    fn test_corrupt_records() {
+        let encoded: IVec = example().into();
+        let mut old = example();
+        old.first_index = None;
+        let old_length = IVec::from(old).len() - 1;
+        // cut short anywhere, other than where older records end
+        for end in (0..encoded.len()).filter(|end| *end != old_length) {
+            assert!(SearchResult::try_from(IVec::from(&encoded[..end])).is_err());
+        }
+        assert!(SearchResult::try_from(IVec::from(&b"{\"url\": 3"[..])).is_err());
+        assert!(SearchResult::try_from(IVec::from(&b"garbage"[..])).is_err());
+
+        // a time far past the end of the calendar
+        let mut buffer = vec![RECORD_FORMAT];
+        for _ in 0..3 {
+            varint::write_bytes(&mut buffer, b"x");
+        }
+        varint::write_i64(&mut buffer, 253_402_300_799);
+        varint::write_u64(&mut buffer, u64::MAX);
+        varint::write_bytes(&mut buffer, b"x");
+        assert!(SearchResult::try_from(IVec::from(buffer)).is_err());
+    }
+
+    #[test]
+    fn test_reads_json_records() {
+        let json = serde_json::to_vec(&example()).unwrap();
+        let binary: IVec = example().into();
+        assert!(binary.len() < json.len());
+        // the binary record is its text plus a few bytes of lengths and times
+        let page = example();
+        let text = page.url.len() + page.title.len() + page.description.len() + page.ingester.len();
+        assert!(binary.len() <= text + 24, "{} bytes", binary.len());
+
+        let decoded = SearchResult::try_from(IVec::from(json)).unwrap();
+        assert_eq!(decoded.last_index, example().last_index);
     }
 }
@@ -5,6 +5,32 @@ pub enum Error {
     // index errors
     #[error("Bad Index Record")]
     BadIndexRecord,
+    #[error(
+        "Index is version {0} but this version of ceridwen only understands up to version {1}"
+    )]
+    UnsupportedSchema(u32, u32),
+    #[error("Invalid collection: {0}")]
+    InvalidCollection(String),
+
+    // command line errors
+    #[error("Invalid command: {0}")]
+    InvalidCommand(String),
+
+    // preference errors
+    #[error("Invalid preference: {0}")]
+    InvalidPreference(String),
+
+    // history errors
+    #[error("Invalid saved search: {0}")]
+    InvalidSavedSearch(String),
+
+    // feed errors
+    #[error("Invalid feed: {0}")]
+    InvalidFeed(String),
+
+    // export errors
+    #[error("Invalid index export: {0}")]
+    InvalidExport(String),
 
     // url errors
     #[error("Missing host: {0}")]
@@ -13,6 +39,12 @@ pub enum Error {
     // Page loading errors
     #[error("Page not found (404): {0}")]
     PageNotFound(String),
+    #[error("Not requesting anything from {0} for a while, it has failed too many times")]
+    HostUnavailable(String),
+    #[error("Could not get the robots.txt for {0}, not fetching anything from it for now")]
+    RobotsUnavailable(String),
+    #[error("Response from {0} is bigger than the {1} bytes we will read")]
+    ResponseTooLarge(String, u64),
 
     // Ingester errors
     #[error("Unknown ingester {0}")]
@@ -33,6 +65,8 @@ pub enum Error {
     // Std lib errors
     #[error("Could not parse int: {0:?}")]
     ParseInt(#[from] std::num::ParseIntError),
+    #[error("Could not parse bool: {0:?}")]
+    ParseBool(#[from] std::str::ParseBoolError),
     #[error("Could not parse url: {0:?}")]
     UrlParsing(#[from] url::ParseError),
     #[error("IO Error: {0:?}")]
@@ -55,10 +89,14 @@ pub enum Error {
     ReqwestError(#[from] reqwest::Error),
     #[error("RSS error: {0:?}")]
     RSSError(#[from] rss::Error),
+    #[error("Atom error: {0:?}")]
+    AtomError(#[from] atom_syndication::Error),
     #[error("Could not join: {0:?}")]
     TokioJoin(#[from] tokio::task::JoinError),
     #[error("Could not parse xml: {0:?}")]
     InvalidXML(#[from] quick_xml::Error),
+    #[error("Json error: {0:?}")]
+    Json(#[from] serde_json::Error),
     #[error("Template error: {0:?}")]
     Tera(#[from] tera::Error),
 
@@ -85,7 +123,12 @@ impl PartialEq for Error {
 
 impl actix_web::ResponseError for Error {
     fn status_code(&self) -> actix_web::http::StatusCode {
-        actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            Error::InvalidCollection(_) => actix_web::http::StatusCode::NOT_FOUND,
+            Error::InvalidPreference(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::InvalidSavedSearch(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            _ => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
     }
 
     fn error_response(&self) -> actix_web::HttpResponse<actix_web::body::BoxBody> {
@@ -0,0 +1,279 @@
+//! What has been searched for, and searches that have been saved to be run again later.
+//!
+//! Saved searches remember the urls of every result they have shown, so running one again can point out which
+//! results are new since last time. History entries are keyed by a sled generated id, which always goes up, so the
+//! oldest entries are first. Ids jump forward each time the database is opened, so trimming counts entries rather
+//! than comparing ids.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::data::SearchHit;
+use crate::error::Error;
+use crate::utils;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub query: String,
+    /// None when every collection was searched
+    pub collection: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub time: time::OffsetDateTime,
+    /// number of results the search found
+    pub results: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SavedSearch {
+    pub name: String,
+    pub query: String,
+    pub collection: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created: time::OffsetDateTime,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub last_run: Option<time::OffsetDateTime>,
+    /// urls of every result this search has shown
+    #[serde(default)]
+    pub seen: BTreeSet<String>,
+}
+
+pub struct History {
+    searches: sled::Tree,
+    saved: sled::Tree,
+    db: sled::Db,
+    /// most searches to remember, 0 to remember none
+    max_entries: usize,
+}
+
+impl History {
+    pub fn history_path() -> PathBuf {
+        utils::system_root().join("history_index")
+    }
+
+    pub fn open(max_entries: usize) -> Result<Self, Error> {
+        History::from_db(sled::open(History::history_path())?, max_entries)
+    }
+
+    fn from_db(db: sled::Db, max_entries: usize) -> Result<Self, Error> {
+        Ok(History {
+            searches: db.open_tree("searches")?,
+            saved: db.open_tree("saved")?,
+            db,
+            max_entries,
+        })
+    }
+
+    /// Remember a search, forgetting the oldest ones once there are more than max_entries.
+    pub fn record(
+        &self,
+        query: &str,
+        collection: Option<&str>,
+        results: usize,
+    ) -> Result<(), Error> {
+        if self.max_entries == 0 {
+            return Ok(());
+        }
+        let entry = HistoryEntry {
+            query: query.to_string(),
+            collection: collection.map(str::to_string),
+            time: time::OffsetDateTime::now_utc(),
+            results,
+        };
+        let id = self.db.generate_id()?;
+        self.searches
+            .insert(id.to_be_bytes(), serde_json::to_vec(&entry)?)?;
+
+        while self.searches.len() > self.max_entries {
+            if self.searches.pop_min()?.is_none() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// The most recent searches, newest first
+    pub fn recent(&self, limit: usize) -> Result<Vec<HistoryEntry>, Error> {
+        let mut result = Vec::new();
+        for value in self.searches.iter().values().rev().take(limit) {
+            result.push(serde_json::from_slice(&value?)?);
+        }
+        Ok(result)
+    }
+
+    pub fn clear(&self) -> Result<(), Error> {
+        self.searches.clear()?;
+        self.searches.flush()?;
+        Ok(())
+    }
+
+    /// Save a search under a name, replacing any saved search that already has that name.
+    pub fn save_search(
+        &self,
+        name: &str,
+        query: &str,
+        collection: Option<&str>,
+    ) -> Result<SavedSearch, Error> {
+        let name = name.trim();
+        if name.is_empty() || query.trim().is_empty() {
+            return Err(Error::InvalidSavedSearch(
+                "saved searches need a name and a query".to_string(),
+            ));
+        }
+        let search = SavedSearch {
+            name: name.to_string(),
+            query: query.to_string(),
+            collection: collection.map(str::to_string),
+            created: time::OffsetDateTime::now_utc(),
+            last_run: None,
+            seen: BTreeSet::new(),
+        };
+        self.store_saved(&search)?;
+        Ok(search)
+    }
+
+    fn store_saved(&self, search: &SavedSearch) -> Result<(), Error> {
+        self.saved
+            .insert(search.name.as_bytes(), serde_json::to_vec(search)?)?;
+        Ok(())
+    }
+
+    pub fn saved_search(&self, name: &str) -> Result<Option<SavedSearch>, Error> {
+        Ok(self
+            .saved
+            .get(name.as_bytes())?
+            .map(|v| serde_json::from_slice(&v))
+            .transpose()?)
+    }
+
+    /// Every saved search, by name
+    pub fn saved_searches(&self) -> Result<Vec<SavedSearch>, Error> {
+        let mut result = Vec::new();
+        for value in self.saved.iter().values() {
+            result.push(serde_json::from_slice(&value?)?);
+        }
+        Ok(result)
+    }
+
+    /// Returns false if there was no saved search with this name
+    pub fn delete_saved(&self, name: &str) -> Result<bool, Error> {
+        Ok(self.saved.remove(name.as_bytes())?.is_some())
+    }
+
+    /// Record that a saved search has been run and shown these results. Returns whether each result is new, which
+    /// is every result the first time a search is run.
+    pub fn mark_seen(&self, name: &str, results: &[SearchHit]) -> Result<Vec<bool>, Error> {
+        let mut search = self
+            .saved_search(name)?
+            .ok_or_else(|| Error::InvalidSavedSearch(format!("{name} does not exist")))?;
+
+        let new: Vec<bool> = results
+            .iter()
+            .map(|hit| search.seen.insert(hit.page.url.clone()))
+            .collect();
+        search.last_run = Some(time::OffsetDateTime::now_utc());
+        self.store_saved(&search)?;
+        Ok(new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::SearchHit;
+    use crate::history::History;
+
+    fn temporary_history(max_entries: usize) -> History {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        History::from_db(db, max_entries).unwrap()
+    }
+
+    #[test]
+    fn test_history_is_trimmed() {
+        let history = temporary_history(3);
+        for query in ["a", "b", "c", "d", "e"] {
+            history.record(query, None, 1).unwrap();
+        }
+        let queries: Vec<String> = history
+            .recent(10)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.query)
+            .collect();
+        assert_eq!(queries, ["e", "d", "c"]);
+
+        history.clear().unwrap();
+        assert!(history.recent(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_history_survives_reopening() {
+        let path =
+            std::env::temp_dir().join(format!("ceridwen-history-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        // sled's background threads can hold on to the db for a moment after it is dropped
+        let open = || {
+            for _ in 0..100 {
+                if let Ok(db) = sled::open(&path) {
+                    return History::from_db(db, 3).unwrap();
+                }
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            panic!("could not open {path:?} again");
+        };
+        for query in ["a", "b", "c", "d"] {
+            open().record(query, None, 1).unwrap();
+        }
+        let history = open();
+        let queries: Vec<String> = history
+            .recent(10)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.query)
+            .collect();
+        drop(history);
+        let _ = std::fs::remove_dir_all(&path);
+        assert_eq!(queries, ["d", "c", "b"]);
+    }
+
+    #[test]
+    fn test_new_results() {
+        let history = temporary_history(0);
+        history.save_search("otters", "otter", None).unwrap();
+        assert!(history.save_search("", "otter", None).is_err());
+
+        let now = time::OffsetDateTime::now_utc();
+        let first = history
+            .mark_seen(
+                "otters",
+                &[
+                    SearchHit::example("http://a/", now),
+                    SearchHit::example("http://b/", now),
+                ],
+            )
+            .unwrap();
+        assert_eq!(first, [true, true]);
+
+        let second = history
+            .mark_seen(
+                "otters",
+                &[
+                    SearchHit::example("http://c/", now),
+                    SearchHit::example("http://a/", now),
+                ],
+            )
+            .unwrap();
+        assert_eq!(second, [true, false]);
+        assert!(history
+            .saved_search("otters")
+            .unwrap()
+            .unwrap()
+            .last_run
+            .is_some());
+
+        // nothing is recorded with a history size of 0
+        history.record("otter", None, 2).unwrap();
+        assert!(history.recent(10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,87 @@
+//! Which search results were opened for which searches, so pages people actually choose move up the results.
+//!
+//! Counts are kept under the page id, for the page as a whole, and under the page id followed by the normalised query
+//! for clicks from a particular search. Keeping the page id first means everything for a page can be removed with a
+//! single prefix scan.
+
+use crate::error::Error;
+
+use super::collection::Collection;
+
+/// How much clicks from any search count towards a page's score
+const PAGE_CLICK_WEIGHT: f64 = 0.25;
+/// How much clicks from the same search count towards a page's score
+const QUERY_CLICK_WEIGHT: f64 = 1.0;
+
+fn query_key(page_id: &[u8], query: &str) -> Vec<u8> {
+    let mut key = page_id.to_vec();
+    key.extend_from_slice(query.as_bytes());
+    key
+}
+
+fn decode(value: &[u8]) -> u64 {
+    value.try_into().map(u64::from_be_bytes).unwrap_or(0)
+}
+
+fn increment(db: &sled::Db, key: &[u8]) -> Result<(), Error> {
+    db.update_and_fetch(key, |old| {
+        let count = old.map(decode).unwrap_or(0) + 1;
+        Some(count.to_be_bytes().to_vec())
+    })?;
+    Ok(())
+}
+
+fn count(db: &sled::Db, key: &[u8]) -> Result<u64, Error> {
+    Ok(db.get(key)?.map(|v| decode(&v)).unwrap_or(0))
+}
+
+/// Record that a page was opened from the results of a query. The query should already be normalised.
+pub(super) fn record(collection: &Collection, page_id: &[u8], query: &str) -> Result<(), Error> {
+    increment(collection.clicks_db(), page_id)?;
+    if !query.is_empty() {
+        increment(collection.clicks_db(), &query_key(page_id, query))?;
+    }
+    Ok(())
+}
+
+pub(super) fn remove(collection: &Collection, page_id: &[u8]) -> Result<(), Error> {
+    let mut batch = sled::Batch::default();
+    for key in collection.clicks_db().scan_prefix(page_id).keys() {
+        batch.remove(key?);
+    }
+    collection.clicks_db().apply_batch(batch)?;
+    Ok(())
+}
+
+/// What to multiply a page's score by for a query, based on how often it has been chosen before. 1.0 for pages that
+/// have never been clicked on.
+pub(super) fn boost(collection: &Collection, page_id: &[u8], query: &str) -> Result<f64, Error> {
+    let page_clicks = count(collection.clicks_db(), page_id)?;
+    if page_clicks == 0 {
+        return Ok(1.0);
+    }
+    let query_clicks = count(collection.clicks_db(), &query_key(page_id, query))?;
+
+    Ok(boost_for(page_clicks, query_clicks))
+}
+
+/// Logarithmic so the first few clicks count for the most, and a page opened every day doesn't swamp everything
+fn boost_for(page_clicks: u64, query_clicks: u64) -> f64 {
+    1.0 + PAGE_CLICK_WEIGHT * (page_clicks as f64).ln_1p()
+        + QUERY_CLICK_WEIGHT * (query_clicks as f64).ln_1p()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index_sled::clicks::boost_for;
+
+    #[test]
+    fn test_boost() {
+        assert_eq!(boost_for(0, 0), 1.0);
+        // clicks from the same search count for more than clicks from other searches
+        assert!(boost_for(3, 3) > boost_for(3, 0));
+        assert!(boost_for(3, 0) > boost_for(0, 0));
+        // but not without limit
+        assert!(boost_for(1000, 1000) < 10.0);
+    }
+}
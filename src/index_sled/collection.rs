@@ -0,0 +1,292 @@
+//! A collection is a complete, separate index with its own storage on disk. Keeping big sources like wikipedia in
+//! their own collection means they can be rebuilt or dropped without touching anything else.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+
+use log::info;
+
+use crate::error::Error;
+use crate::utils::system_root;
+
+use super::index_path;
+use super::schema;
+
+/// The collection used when nothing else is asked for. It lives where the index did before we had collections.
+pub const DEFAULT_COLLECTION: &str = "default";
+
+/// Where every collection other than the default lives
+fn collections_path() -> PathBuf {
+    system_root().join("collections")
+}
+
+fn collection_path(name: &str) -> PathBuf {
+    if name == DEFAULT_COLLECTION {
+        index_path()
+    } else {
+        collections_path().join(name)
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Collection {
+    pub(super) name: String,
+    pub(super) path: PathBuf,
+
+    page_db: sled::Db,
+    page_url_db: sled::Db,
+    /// Forward index of page id to the words stored for that page, so postings can be removed without scanning every
+    /// word in the index.
+    page_words_db: sled::Db,
+    postings_db: sled::Db,
+    /// Highest count each word has on any page. Used to skip pages that can't make it into the top results.
+    terms_db: sled::Db,
+    documents_db: sled::Db,
+    /// Urls each page links to
+    links_db: sled::Db,
+    /// Authority of each page worked out from the links
+    rank_db: sled::Db,
+    /// Which results were opened for which searches
+    clicks_db: sled::Db,
+    stats_db: sled::Db,
+    meta_db: sled::Db,
+    /// How each url was served, so the crawler can ask for it again only if it has changed
+    validators_db: sled::Db,
+
+    /// Updating a block of postings means reading it, changing it and writing it back. Only let one thread at a time
+    /// do that so postings don't get lost when several pages with the same word are added at once.
+    pub(super) postings_lock: Mutex<()>,
+
+    /// Goes up every time something in the collection changes, so anything holding on to search results can tell
+    /// they are out of date.
+    generation: AtomicU64,
+}
+
+impl Collection {
+    fn open(name: &str) -> Result<Self, Error> {
+        Collection::open_at(name, collection_path(name), false)
+    }
+
+    /// A collection in a directory of its own that is removed again when it is closed, for tests. It hasn't been
+    /// upgraded to the current schema.
+    #[cfg(test)]
+    pub(super) fn temporary(name: &str) -> Result<Self, Error> {
+        static COUNT: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir()
+            .join(format!("ceridwen-test-{}", std::process::id()))
+            .join(format!("{name}-{}", COUNT.fetch_add(1, Ordering::Relaxed)));
+        Collection::open_at(name, path, true)
+    }
+
+    fn open_at(name: &str, path: PathBuf, temporary: bool) -> Result<Self, Error> {
+        let open = |tree: &str| {
+            sled::Config::new()
+                .path(path.join(tree))
+                .temporary(temporary)
+                .open()
+        };
+        Ok(Collection {
+            name: name.to_string(),
+            page_db: open("page_index")?,
+            page_url_db: open("page_url_index")?,
+            page_words_db: open("page_words_index")?,
+            postings_db: open("postings_index")?,
+            terms_db: open("term_index")?,
+            documents_db: open("document_index")?,
+            links_db: open("link_index")?,
+            rank_db: open("rank_index")?,
+            clicks_db: open("click_index")?,
+            stats_db: open("stats_index")?,
+            meta_db: open("meta_index")?,
+            validators_db: open("validator_index")?,
+            postings_lock: Mutex::new(()),
+            generation: AtomicU64::new(0),
+            path,
+        })
+    }
+
+    pub(super) fn page_db(&self) -> &sled::Db {
+        &self.page_db
+    }
+
+    pub(super) fn page_url_db(&self) -> &sled::Db {
+        &self.page_url_db
+    }
+
+    pub(super) fn page_words_db(&self) -> &sled::Db {
+        &self.page_words_db
+    }
+
+    pub(super) fn postings_db(&self) -> &sled::Db {
+        &self.postings_db
+    }
+
+    pub(super) fn terms_db(&self) -> &sled::Db {
+        &self.terms_db
+    }
+
+    pub(super) fn documents_db(&self) -> &sled::Db {
+        &self.documents_db
+    }
+
+    pub(super) fn links_db(&self) -> &sled::Db {
+        &self.links_db
+    }
+
+    pub(super) fn rank_db(&self) -> &sled::Db {
+        &self.rank_db
+    }
+
+    pub(super) fn clicks_db(&self) -> &sled::Db {
+        &self.clicks_db
+    }
+
+    pub(super) fn stats_db(&self) -> &sled::Db {
+        &self.stats_db
+    }
+
+    pub(super) fn meta_db(&self) -> &sled::Db {
+        &self.meta_db
+    }
+
+    pub(super) fn validators_db(&self) -> &sled::Db {
+        &self.validators_db
+    }
+
+    pub(super) fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Record that the contents of the collection have changed
+    pub(super) fn changed(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Every tree in the collection along with the name of its directory
+    pub(super) fn trees(&self) -> [(&'static str, &sled::Db); 12] {
+        [
+            ("page_index", &self.page_db),
+            ("page_url_index", &self.page_url_db),
+            ("page_words_index", &self.page_words_db),
+            ("postings_index", &self.postings_db),
+            ("term_index", &self.terms_db),
+            ("document_index", &self.documents_db),
+            ("link_index", &self.links_db),
+            ("rank_index", &self.rank_db),
+            ("click_index", &self.clicks_db),
+            ("stats_index", &self.stats_db),
+            ("meta_index", &self.meta_db),
+            ("validator_index", &self.validators_db),
+        ]
+    }
+}
+
+/// sled only lets a database be opened once per process, so every collection we open is kept here and shared.
+fn open_collections() -> &'static Mutex<HashMap<String, Arc<Collection>>> {
+    static OPEN: OnceLock<Mutex<HashMap<String, Arc<Collection>>>> = OnceLock::new();
+    OPEN.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Open a collection, creating it if it doesn't exist and upgrading it if it was written by an older version.
+pub(super) fn open(name: &str) -> Result<Arc<Collection>, Error> {
+    check_name(name)?;
+
+    let mut open = open_collections().lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(collection) = open.get(name) {
+        return Ok(collection.clone());
+    }
+
+    info!("Opening collection {name}");
+    let collection = Collection::open(name)?;
+    schema::ensure_current(&collection)?;
+
+    let collection = Arc::new(collection);
+    open.insert(name.to_string(), collection.clone());
+    Ok(collection)
+}
+
+/// True if the collection has been created on disk. The default collection always exists.
+pub(super) fn exists(name: &str) -> bool {
+    name == DEFAULT_COLLECTION || (check_name(name).is_ok() && collection_path(name).exists())
+}
+
+/// Delete a collection and everything in it from disk.
+pub(super) fn delete(name: &str) -> Result<(), Error> {
+    check_name(name)?;
+    if name == DEFAULT_COLLECTION {
+        return Err(Error::InvalidCollection(format!(
+            "the {DEFAULT_COLLECTION} collection can not be deleted"
+        )));
+    }
+
+    let open = open_collections().lock().unwrap_or_else(|e| e.into_inner());
+    if open.contains_key(name) {
+        return Err(Error::InvalidCollection(format!(
+            "{name} is in use and can not be deleted"
+        )));
+    }
+
+    let path = collection_path(name);
+    if !path.exists() {
+        return Err(Error::InvalidCollection(format!("{name} does not exist")));
+    }
+    fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+/// Names of every collection on disk. The default collection is always included.
+pub fn collection_names() -> Result<Vec<String>, Error> {
+    let mut names = vec![DEFAULT_COLLECTION.to_string()];
+    let path = collections_path();
+    if path.exists() {
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.file_type()?.is_dir()
+                && check_name(&name).is_ok()
+                && name != DEFAULT_COLLECTION
+            {
+                names.push(name);
+            }
+        }
+    }
+    names[1..].sort();
+    Ok(names)
+}
+
+/// Collection names become directory names, so keep them simple.
+fn check_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidCollection(format!(
+            "{name:?} is not a valid name. Use letters, numbers, '-' and '_'"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index_sled::collection::check_name;
+
+    #[test]
+    fn test_check_name() {
+        assert!(check_name("wikipedia").is_ok());
+        assert!(check_name("work-docs").is_ok());
+        assert!(check_name("personal_2").is_ok());
+
+        assert!(check_name("").is_err());
+        assert!(check_name("../escape").is_err());
+        assert!(check_name("has space").is_err());
+    }
+}
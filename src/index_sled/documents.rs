@@ -0,0 +1,66 @@
+//! Compressed copies of the full text of each page, keyed by page id. This lets us rebuild the word index or show
+//! more than the short description without fetching the page again.
+
+use crate::error::Error;
+
+use super::collection::Collection;
+
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Store the text of a page, cut down to at most max_size bytes. A max_size of zero means don't store the text.
+pub(super) fn store(
+    collection: &Collection,
+    page_id: &[u8],
+    text: &str,
+    max_size: usize,
+) -> Result<(), Error> {
+    if max_size == 0 {
+        collection.documents_db().remove(page_id)?;
+        return Ok(());
+    }
+
+    let compressed = zstd::encode_all(truncate(text, max_size).as_bytes(), COMPRESSION_LEVEL)?;
+    collection.documents_db().insert(page_id, compressed)?;
+    Ok(())
+}
+
+pub(super) fn load(collection: &Collection, page_id: &[u8]) -> Result<Option<String>, Error> {
+    match collection.documents_db().get(page_id)? {
+        Some(compressed) => {
+            let text = zstd::decode_all(compressed.as_ref())?;
+            Ok(Some(String::from_utf8(text)?))
+        }
+        None => Ok(None),
+    }
+}
+
+pub(super) fn remove(collection: &Collection, page_id: &[u8]) -> Result<(), Error> {
+    collection.documents_db().remove(page_id)?;
+    Ok(())
+}
+
+/// Cut text down to at most max_size bytes without splitting a character.
+fn truncate(text: &str, max_size: usize) -> &str {
+    if text.len() <= max_size {
+        return text;
+    }
+    let mut end = max_size;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index_sled::documents::truncate;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 100), "short");
+        assert_eq!(truncate("exactly", 7), "exactly");
+        assert_eq!(truncate("longer text", 6), "longer");
+        // é is two bytes so cutting in the middle of it should drop it
+        assert_eq!(truncate("café", 4), "caf");
+    }
+}
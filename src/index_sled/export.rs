@@ -0,0 +1,294 @@
+//! Portable dump of the index, so it can be moved between machines and sled versions.
+//!
+//! The format is JSON Lines. The first line is an `ExportHeader` and every line after that is a single
+//! `ExportRecord`. Postings (the words found on each page) are optional, without them an import indexes the stored
+//! text of each page, or only its title and description if there is none. Records only include the text and links of
+//! a page if it has any.
+
+use std::io::BufRead;
+use std::io::Write;
+
+use log::info;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::data::SearchResult;
+use crate::error::Error;
+use crate::utils::text_tools::tokenise;
+
+use super::decode_id;
+use super::documents;
+use super::links;
+use super::postings;
+use super::word_counts;
+use super::Index;
+use super::PAGE_WORDS_SEPARATOR;
+use super::PROGRESS_INTERVAL;
+
+const EXPORT_FORMAT: &str = "ceridwen-index";
+const EXPORT_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportHeader {
+    format: String,
+    version: u32,
+    postings: bool,
+    #[serde(with = "time::serde::rfc3339")]
+    created: time::OffsetDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportRecord {
+    url: String,
+    title: String,
+    description: String,
+    #[serde(with = "time::serde::rfc3339")]
+    last_index: time::OffsetDateTime,
+    ingester: String,
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    first_index: Option<time::OffsetDateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    words: Option<Vec<(String, u64)>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    links: Vec<url::Url>,
+    /// the full text kept for the page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+}
+
+impl Index {
+    /// Write every page in the index to writer. Returns the number of pages written.
+    pub fn export<W: Write>(&self, writer: &mut W, include_postings: bool) -> Result<usize, Error> {
+        let header = ExportHeader {
+            format: EXPORT_FORMAT.to_string(),
+            version: EXPORT_VERSION,
+            postings: include_postings,
+            created: time::OffsetDateTime::now_utc(),
+        };
+        serde_json::to_writer(&mut *writer, &header)?;
+        writer.write_all(b"\n")?;
+
+        let mut count = 0;
+        for row in self.collection.page_db().iter() {
+            let (id, value) = row?;
+            let page = SearchResult::try_from(value)?;
+
+            let words = if include_postings {
+                self.read_words(&id, &page.url)?
+            } else {
+                None
+            };
+
+            let record = ExportRecord {
+                url: page.url,
+                title: page.title,
+                description: page.description,
+                last_index: page.last_index,
+                ingester: page.ingester,
+                first_index: page.first_index,
+                words,
+                links: links::load(&self.collection, &id)?,
+                text: documents::load(&self.collection, &id)?,
+            };
+            serde_json::to_writer(&mut *writer, &record)?;
+            writer.write_all(b"\n")?;
+
+            count += 1;
+            if count % PROGRESS_INTERVAL == 0 {
+                info!("Exported {count} pages");
+            }
+        }
+        writer.flush()?;
+
+        Ok(count)
+    }
+
+    /// Read pages written by [`Index::export`] into this index. Pages that are already in the index are replaced.
+    /// Returns the number of pages read.
+    pub fn import<R: BufRead>(&self, reader: R) -> Result<usize, Error> {
+        let mut lines = reader.lines();
+
+        let header: ExportHeader = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => return Err(Error::InvalidExport("empty file".to_string())),
+        };
+        if header.format != EXPORT_FORMAT {
+            return Err(Error::InvalidExport(format!(
+                "unknown format {}",
+                header.format
+            )));
+        }
+        if header.version > EXPORT_VERSION {
+            return Err(Error::InvalidExport(format!(
+                "version {} is newer than we understand ({})",
+                header.version, EXPORT_VERSION
+            )));
+        }
+        info!(
+            "Importing index exported at {} (postings included: {})",
+            header.created, header.postings
+        );
+
+        let mut count = 0;
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: ExportRecord = serde_json::from_str(&line)?;
+
+            self.delete_page(&record.url)?;
+
+            let words = match (record.words, record.text.as_ref()) {
+                (Some(mut words), _) => {
+                    // anything else would break the layout of the postings keys, and could never be searched for
+                    words.retain(|(word, _)| {
+                        let valid = is_indexed_word(word);
+                        if !valid {
+                            warn!(
+                                "Skipping word {word:?} for {} that we would never index",
+                                record.url
+                            );
+                        }
+                        valid
+                    });
+                    words
+                }
+                (None, Some(text)) => word_counts(&record.title, text),
+                (None, None) => word_counts(&record.title, &record.description),
+            };
+            let search_result = SearchResult {
+                url: record.url,
+                title: record.title,
+                description: record.description,
+                last_index: record.last_index,
+                ingester: record.ingester,
+                first_index: record.first_index,
+            };
+            let id = self.store_search_result(&search_result)?;
+            links::store(&self.collection, &id, &record.links)?;
+            if let Some(text) = record.text.as_ref() {
+                // it was already cut down to size when it was first stored
+                documents::store(&self.collection, &id, text, usize::MAX)?;
+            }
+            self.store_words(id, words)?;
+
+            count += 1;
+            if count % PROGRESS_INTERVAL == 0 {
+                info!("Imported {count} pages");
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Read the words and counts stored for a page.
+    fn read_words(&self, page_id: &[u8], url: &str) -> Result<Option<Vec<(String, u64)>>, Error> {
+        let page_words = match self.collection.page_words_db().get(page_id)? {
+            Some(w) => w,
+            None => {
+                warn!("No word list for {url}, exporting it without postings");
+                return Ok(None);
+            }
+        };
+
+        let mut result = Vec::new();
+        for word in page_words.split(|b| *b == PAGE_WORDS_SEPARATOR) {
+            if word.is_empty() {
+                continue;
+            }
+            if let Some(count) = postings::get(&self.collection, word, decode_id(page_id)?)? {
+                result.push((String::from_utf8(word.to_vec())?, count));
+            }
+        }
+
+        Ok(Some(result))
+    }
+}
+
+/// Whether the word is one that tokenising a page could produce.
+fn is_indexed_word(word: &str) -> bool {
+    !word.is_empty() && tokenise(word) == [word]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::Page;
+    use crate::index_sled::links;
+    use crate::index_sled::Index;
+
+    async fn exported_index() -> Index {
+        let index = Index::temporary().unwrap();
+        let page = Page {
+            url: url::Url::parse("https://example.com/").unwrap(),
+            title: "Orchards".to_string(),
+            // the word we search for is past the end of the description
+            content: format!("{} quince", "apples and pears ".repeat(20)),
+            ingester: "test".to_string(),
+            links: vec![url::Url::parse("https://example.org/").unwrap()],
+        };
+        index
+            .add_page(&page, time::Duration::ZERO, usize::MAX)
+            .await
+            .unwrap();
+        index
+    }
+
+    async fn round_trip(include_postings: bool) {
+        let index = exported_index().await;
+        let mut exported = Vec::new();
+        assert_eq!(index.export(&mut exported, include_postings).unwrap(), 1);
+
+        let imported = Index::temporary().unwrap();
+        assert_eq!(imported.import(exported.as_slice()).unwrap(), 1);
+
+        let hits = imported.search("quince").await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].page.title, "Orchards");
+        let text = imported.page_text(hits[0].id).unwrap().unwrap();
+        assert!(text.ends_with(" quince"));
+        assert_eq!(
+            links::load(&imported.collection, &hits[0].id.to_be_bytes()).unwrap(),
+            vec![url::Url::parse("https://example.org/").unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        round_trip(true).await;
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_without_postings() {
+        round_trip(false).await;
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_bad_words() {
+        let header = r#"{"format":"ceridwen-index","version":1,"postings":true,"created":"2026-01-01T00:00:00Z"}"#;
+        let record = r#"{"url":"https://example.com/","title":"Orchards","description":"","last_index":"2026-01-01T00:00:00Z","ingester":"test","words":[["quince",2],["bad=word",1],["Quince",1],["two words",1],["",1]]}"#;
+        let imported = Index::temporary().unwrap();
+        assert_eq!(
+            imported
+                .import(format!("{header}\n{record}\n").as_bytes())
+                .unwrap(),
+            1
+        );
+
+        let hits = imported.search("quince").await.unwrap();
+        assert_eq!(hits.len(), 1);
+        let page_words = imported
+            .collection
+            .page_words_db()
+            .get(hits[0].id.to_be_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(page_words.as_ref(), b"quince");
+        assert!(imported.search("bad").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! The links between pages, and an authority score for each page worked out from them with PageRank.
+//!
+//! Links are stored by url under the id of the page they are on, as the page they point at may not have been indexed
+//! yet. They are only turned into page ids when the ranks are worked out. Ranks are scaled so the average page has a
+//! rank of 1.0, which keeps them comparable between collections of different sizes.
+
+use std::collections::HashMap;
+
+use log::info;
+
+use crate::error::Error;
+use crate::utils::varint;
+
+use super::collection::Collection;
+use super::decode_id;
+
+/// Set in the meta tree when links have changed since the ranks were last worked out
+const LINKS_CHANGED_KEY: &[u8] = b"links_changed";
+
+/// Chance of following a link rather than jumping to a random page
+const DAMPING: f64 = 0.85;
+const MAX_ITERATIONS: usize = 50;
+/// Stop iterating once no rank moves by more than this
+const TOLERANCE: f64 = 1e-6;
+
+/// Rank of a page we haven't worked one out for yet. This is the average rank.
+pub(super) const DEFAULT_RANK: f64 = 1.0;
+
+pub(super) fn store(
+    collection: &Collection,
+    page_id: &[u8],
+    links: &[url::Url],
+) -> Result<(), Error> {
+    if links.is_empty() {
+        if collection.links_db().remove(page_id)?.is_some() {
+            mark_changed(collection)?;
+        }
+        return Ok(());
+    }
+
+    let mut buffer = Vec::new();
+    for link in links.iter() {
+        varint::write_bytes(&mut buffer, link.as_str().as_bytes());
+    }
+    collection.links_db().insert(page_id, buffer)?;
+    mark_changed(collection)
+}
+
+pub(super) fn remove(collection: &Collection, page_id: &[u8]) -> Result<(), Error> {
+    collection.rank_db().remove(page_id)?;
+    if collection.links_db().remove(page_id)?.is_some() {
+        mark_changed(collection)?;
+    }
+    Ok(())
+}
+
+pub(super) fn load(collection: &Collection, page_id: &[u8]) -> Result<Vec<url::Url>, Error> {
+    match collection.links_db().get(page_id)? {
+        Some(value) => decode_links(&value)?
+            .into_iter()
+            .map(|link| {
+                Ok(url::Url::parse(
+                    std::str::from_utf8(link).map_err(|_| Error::BadIndexRecord)?,
+                )?)
+            })
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn mark_changed(collection: &Collection) -> Result<(), Error> {
+    collection.meta_db().insert(LINKS_CHANGED_KEY, &[1])?;
+    Ok(())
+}
+
+pub(super) fn rank(collection: &Collection, page_id: &[u8]) -> Result<f64, Error> {
+    Ok(collection
+        .rank_db()
+        .get(page_id)?
+        .and_then(|v| v.as_ref().try_into().ok())
+        .map(f64::from_be_bytes)
+        .unwrap_or(DEFAULT_RANK))
+}
+
+fn decode_links(value: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let mut input = value;
+    let mut result = Vec::new();
+    while !input.is_empty() {
+        result.push(varint::read_bytes(&mut input).ok_or(Error::BadIndexRecord)?);
+    }
+    Ok(result)
+}
+
+/// Work out the rank of every page from the links between them, if the links have changed since it was last done
+/// or force is set. Returns the number of pages ranked, 0 if nothing needed doing.
+pub(super) fn update_ranks(collection: &Collection, force: bool) -> Result<usize, Error> {
+    if !force && !collection.meta_db().contains_key(LINKS_CHANGED_KEY)? {
+        return Ok(0);
+    }
+    // cleared first so links added while we work are picked up next time
+    collection.meta_db().remove(LINKS_CHANGED_KEY)?;
+
+    let mut ids: Vec<u64> = Vec::new();
+    for key in collection.page_db().iter().keys() {
+        ids.push(decode_id(&key?)?);
+    }
+    let positions: HashMap<u64, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+    info!(
+        "Ranking {} pages in collection {}",
+        ids.len(),
+        collection.name
+    );
+
+    // outgoing links for each page, as positions in ids
+    let mut outgoing: Vec<Vec<u32>> = vec![Vec::new(); ids.len()];
+    for row in collection.links_db().iter() {
+        let (key, value) = row?;
+        let from = match positions.get(&decode_id(&key)?) {
+            Some(p) => *p,
+            None => continue,
+        };
+        for url in decode_links(&value)? {
+            if let Some(to_id) = collection.page_url_db().get(url)? {
+                if let Some(to) = positions.get(&decode_id(&to_id)?) {
+                    if *to != from {
+                        outgoing[from].push(*to as u32);
+                    }
+                }
+            }
+        }
+    }
+    // a page linking to another several times only counts once
+    let mut link_count = 0;
+    for links in outgoing.iter_mut() {
+        links.sort_unstable();
+        links.dedup();
+        link_count += links.len();
+    }
+    info!("Found {link_count} links between pages");
+
+    let ranks = page_rank(&outgoing);
+
+    let mut batch = sled::Batch::default();
+    collection.rank_db().clear()?;
+    for (id, rank) in ids.iter().zip(ranks.iter()) {
+        batch.insert(&id.to_be_bytes(), &rank.to_be_bytes());
+    }
+    collection.rank_db().apply_batch(batch)?;
+    collection.rank_db().flush()?;
+    // ranks change the order of search results, so anything holding on to results needs to know
+    collection.changed();
+
+    Ok(ids.len())
+}
+
+/// PageRank over a graph given as the outgoing links of each node. Ranks are scaled so they average 1.0.
+fn page_rank(outgoing: &[Vec<u32>]) -> Vec<f64> {
+    let count = outgoing.len();
+    if count == 0 {
+        return Vec::new();
+    }
+
+    let n = count as f64;
+    let mut ranks = vec![1.0 / n; count];
+    for iteration in 0..MAX_ITERATIONS {
+        // pages without links share their rank with everyone
+        let dangling: f64 = outgoing
+            .iter()
+            .zip(ranks.iter())
+            .filter(|(links, _)| links.is_empty())
+            .map(|(_, rank)| rank)
+            .sum();
+
+        let base = (1.0 - DAMPING) / n + DAMPING * dangling / n;
+        let mut next = vec![base; count];
+        for (from, links) in outgoing.iter().enumerate() {
+            if links.is_empty() {
+                continue;
+            }
+            let share = DAMPING * ranks[from] / links.len() as f64;
+            for to in links.iter() {
+                next[*to as usize] += share;
+            }
+        }
+
+        let change = next
+            .iter()
+            .zip(ranks.iter())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        ranks = next;
+        if change < TOLERANCE / n {
+            info!("Ranks settled after {} iterations", iteration + 1);
+            break;
+        }
+    }
+
+    ranks.iter().map(|r| r * n).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index_sled::links::page_rank;
+
+    #[test]
+    fn test_page_rank() {
+        // 0 and 1 link to each other, 2 and 3 link to 0, nothing links to 2, 3 or 4
+        let outgoing = vec![vec![1], vec![0], vec![0], vec![0], vec![]];
+        let ranks = page_rank(&outgoing);
+
+        let average: f64 = ranks.iter().sum::<f64>() / ranks.len() as f64;
+        assert!((average - 1.0).abs() < 1e-9, "average was {average}");
+        assert!(ranks[0] > ranks[1]);
+        assert!(ranks[1] > ranks[2]);
+        assert!((ranks[2] - ranks[4]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_page_rank_without_links() {
+        let ranks = page_rank(&[vec![], vec![], vec![]]);
+        for rank in ranks.iter() {
+            assert!((rank - 1.0).abs() < 1e-9);
+        }
+    }
+}
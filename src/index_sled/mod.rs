@@ -1,104 +1,184 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::path::PathBuf;
-use std::sync::OnceLock;
+use std::sync::Arc;
 
 use log::debug;
 use log::info;
 use log::warn;
 use sled::IVec;
+use url::Url;
 
 use crate::data::Page;
+use crate::data::SearchHit;
 use crate::data::SearchResult;
 use crate::error::Error;
+use crate::preferences::Preferences;
+use crate::utils::percentage::percentage;
 use crate::utils::system_root;
 use crate::utils::text_tools::count_words;
 use crate::utils::text_tools::filter;
+use crate::utils::text_tools::normalise_query;
 use crate::utils::text_tools::tokenise;
 
+mod clicks;
+mod collection;
+mod documents;
+mod export;
+mod links;
+mod postings;
+mod schema;
+mod stats;
+mod top_k;
+mod validators;
+
+pub use collection::collection_names;
+pub use collection::DEFAULT_COLLECTION;
+pub use stats::IndexStats;
+
+use collection::Collection;
+use top_k::Cursor;
+
 pub fn index_path() -> PathBuf {
     system_root().join("index")
 }
 
-fn word_db() -> &'static sled::Db {
-    static DB: OnceLock<sled::Db> = OnceLock::new();
-    DB.get_or_init(|| sled::open(index_path().join("word_index")).unwrap())
-}
+const PAGE_WORDS_SEPARATOR: u8 = b' ';
 
-const WORD_KEY_SEPARATOR: u8 = b'=';
+/// Log progress every this many pages for long running operations
+const PROGRESS_INTERVAL: usize = 10_000;
 
-fn page_db() -> &'static sled::Db {
-    static DB: OnceLock<sled::Db> = OnceLock::new();
-    DB.get_or_init(|| sled::open(index_path().join("page_index")).unwrap())
-}
+/// Most results a search will return
+const MAX_RESULTS: usize = 100;
 
-fn page_url_db() -> &'static sled::Db {
-    static DB: OnceLock<sled::Db> = OnceLock::new();
-    DB.get_or_init(|| sled::open(index_path().join("page_url_index")).unwrap())
-}
+/// Number of the best matches on words alone that are then ranked again with their link rank. A page needs to match
+/// the words reasonably well before being well linked to can help it.
+const RANK_CANDIDATES: usize = 1000;
 
+/// How much a page's link rank counts compared to how well it matches the words
+const RANK_WEIGHT: f64 = 1.0;
+
+/// Handle on a single collection. Cloning this is cheap, every clone shares the same storage.
 #[derive(Debug, Clone)]
-pub struct Index {}
+pub struct Index {
+    collection: Arc<Collection>,
+}
 
 impl Index {
+    /// Open the default collection
     pub async fn load() -> Result<Self, Error> {
-        Ok(Index {})
-    }
-
-    pub async fn search(&self, search_string: &str) -> Result<Vec<SearchResult>, Error> {
-        let words = tokenise(search_string);
-        info!("Searching for matches to: {words:?}");
-        let mut possible_pages: HashMap<u64, u64> = HashMap::new();
-        for word in words.into_iter() {
-            let mut word_bytes = word.as_bytes().to_vec();
-            word_bytes.push(WORD_KEY_SEPARATOR);
-            let num_bytes = word_bytes.len();
-            info!("scanning for {}=", word);
-            for row in word_db().scan_prefix(word_bytes) {
-                if let Ok((key, value)) = row {
-                    let id = u64::from_be_bytes(
-                        key[num_bytes..]
-                            .try_into()
-                            .unwrap_or_else(|_| panic!("could not decode key {:?}", key)),
-                    );
-                    let count = u64::from_be_bytes(value[..].try_into().unwrap());
-
-                    *possible_pages.entry(id).or_insert(0) += count;
-                } else {
-                    return Err(row.unwrap_err().into());
-                }
-            }
+        Index::open(DEFAULT_COLLECTION).await
+    }
+
+    /// Open a named collection, creating it if it doesn't exist yet.
+    pub async fn open(name: &str) -> Result<Self, Error> {
+        let name = name.to_string();
+        let collection = tokio::task::spawn_blocking(move || collection::open(&name)).await??;
+        Ok(Index { collection })
+    }
+
+    /// Open a collection that has already been created. Use this for names that come from users so a typo doesn't
+    /// leave an empty collection behind.
+    pub async fn open_existing(name: &str) -> Result<Self, Error> {
+        if !collection::exists(name) {
+            return Err(Error::InvalidCollection(format!("{name} does not exist")));
         }
-        info!("Found {} possible pages", possible_pages.len());
+        Index::open(name).await
+    }
+
+    /// Open every collection on disk.
+    pub async fn open_all() -> Result<Vec<Self>, Error> {
+        let mut result = Vec::new();
+        for name in tokio::task::spawn_blocking(collection_names).await??.iter() {
+            result.push(Index::open(name).await?);
+        }
+        Ok(result)
+    }
+
+    /// A collection of its own that is thrown away when it is dropped, for tests
+    #[cfg(test)]
+    pub(crate) fn temporary() -> Result<Self, Error> {
+        let collection = Collection::temporary("test")?;
+        schema::ensure_current(&collection)?;
+        Ok(Index {
+            collection: Arc::new(collection),
+        })
+    }
 
-        let mut frequencies = possible_pages.iter().collect::<Vec<_>>();
-        frequencies.sort_unstable_by_key(|e| e.1);
-        frequencies.reverse();
-        frequencies.truncate(100);
+    /// Delete a collection and everything in it. The collection must not be open in this process.
+    pub async fn delete_collection(name: &str) -> Result<(), Error> {
+        let name = name.to_string();
+        tokio::task::spawn_blocking(move || collection::delete(&name)).await?
+    }
+
+    pub fn collection_name(&self) -> &str {
+        &self.collection.name
+    }
 
+    /// Changes whenever pages are added to or removed from this collection while it is open.
+    pub fn generation(&self) -> u64 {
+        self.collection.generation()
+    }
+
+    pub async fn search(&self, search_string: &str) -> Result<Vec<SearchHit>, Error> {
+        search_all(
+            std::slice::from_ref(self),
+            search_string,
+            &Preferences::default(),
+        )
+        .await
+    }
+
+    /// Find the pages that best match a list of words, along with their scores, best first. Scores are how often the
+    /// words appear on the page, boosted by how well linked to the page is and how often it has been chosen before.
+    fn score_pages(&self, words: &[String], query: &str) -> Result<Vec<(u64, f64)>, Error> {
+        let mut cursors = Vec::with_capacity(words.len());
+        for word in words.iter() {
+            cursors.push(postings::PostingsCursor::new(
+                &self.collection,
+                word.as_bytes(),
+            )?);
+        }
+
+        let candidates = top_k::top_k(cursors, RANK_CANDIDATES)?;
         info!(
-            "after sorting and truncation got {} pages",
-            frequencies.len()
+            "Found {} pages in {}",
+            candidates.len(),
+            self.collection.name
         );
 
-        let mut result = Vec::new();
-        for (id, _count) in frequencies.into_iter() {
-            let search_result = self.lookup_id(*id)?;
-            match search_result {
-                Some(r) => result.push(r),
-                None => warn!(
-                    "Search result returned id {} which doesn't have a page entry. Index is broke!",
-                    id
-                ),
+        let mut scores = Vec::with_capacity(candidates.len());
+        for (id, count) in candidates.into_iter() {
+            let page_id = id.to_be_bytes();
+            let rank = links::rank(&self.collection, &page_id)?;
+            let clicks = clicks::boost(&self.collection, &page_id, query)?;
+            scores.push((
+                id,
+                count as f64 * (1.0 + RANK_WEIGHT * rank.ln_1p()) * clicks,
+            ));
+        }
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Ok(scores)
+    }
+
+    /// Every page that has at least one of the words, in page id order.
+    fn matching_pages(&self, words: &[String]) -> Result<BTreeSet<u64>, Error> {
+        let mut ids = BTreeSet::new();
+        for word in words.iter() {
+            let mut cursor = postings::PostingsCursor::new(&self.collection, word.as_bytes())?;
+            while let Some((id, _)) = cursor.current() {
+                ids.insert(id);
+                cursor.advance()?;
             }
         }
-        info!("finished looking up details for {} pages", result.len());
-        Ok(result)
+        Ok(ids)
     }
 
     pub async fn add_page(
         &self,
         page: &Page,
         min_update_interval: time::Duration,
+        max_document_size: usize,
     ) -> Result<(), Error> {
         // check if we have the page already, and if its old enough to need an update
         let existing_result = self.look_up_page(page)?;
@@ -110,55 +190,82 @@ impl Index {
                     page.url, search_result.last_index
                 );
                 return Ok(());
-            } else {
-                // TODO: clear existing words for this page?
             }
-
-            (id, search_result)
+            // clear out the old words so anything removed from the page stops matching
+            self.delete_words(&id)?;
+
+            // and refresh the title and description shown in results, which may have changed too
+            let mut updated: SearchResult = page.into();
+            updated.first_index = Some(search_result.first_indexed());
+            let page_data: IVec = updated.clone().into();
+            self.collection.page_db().insert(&id, page_data)?;
+            stats::page_removed(&self.collection, &search_result)?;
+            stats::page_added(&self.collection, &updated)?;
+
+            (id, updated)
         } else {
             self.store_page(page)?
         };
 
         info!("adding {} to word index", page.url);
-        let mut words = tokenise(&page.title);
-        words.append(&mut tokenise(&page.content));
-
-        // debug!("found {} tokens for {}", words.len(), page.url);
-        words = filter(words);
-        debug!("filtered to {} tokens for {}", words.len(), page.url);
-
-        let word_counts = count_words(words);
+        let word_counts = word_counts(&page.title, &page.content);
+        debug!(
+            "found {} distinct words for {}",
+            word_counts.len(),
+            page.url
+        );
 
+        documents::store(&self.collection, &page_id, &page.content, max_document_size)?;
+        links::store(&self.collection, &page_id, &page.links)?;
         self.store_words(page_id, word_counts)
     }
 
+    /// Get the full text we stored for a page, if we kept it.
+    pub fn page_text(&self, id: u64) -> Result<Option<String>, Error> {
+        documents::load(&self.collection, &id.to_be_bytes())
+    }
+
     pub async fn last_index_time(
         &self,
         page: &Page,
     ) -> Result<Option<time::OffsetDateTime>, Error> {
         let url = page.url.to_string();
 
-        let page_id = page_url_db().get(url.bytes().collect::<Vec<_>>())?;
+        let page_id = self
+            .collection
+            .page_url_db()
+            .get(url.bytes().collect::<Vec<_>>())?;
         if page_id.is_none() {
             return Ok(None);
         }
 
-        Ok(page_db()
+        Ok(self
+            .collection
+            .page_db()
             .get(page_id.unwrap())?
-            .map(SearchResult::from)
+            .map(SearchResult::try_from)
+            .transpose()?
             .map(|s| s.last_index))
     }
 
     pub fn look_up_page(&self, page: &Page) -> Result<Option<(IVec, SearchResult)>, Error> {
         let url = page.url.to_string();
 
-        let page_id = page_url_db().get(url.bytes().collect::<Vec<_>>())?;
+        let page_id = self
+            .collection
+            .page_url_db()
+            .get(url.bytes().collect::<Vec<_>>())?;
         if page_id.is_none() {
             return Ok(None);
         }
         let page_id = page_id.unwrap();
 
-        let page = page_db().get(&page_id)?.map(SearchResult::from);
+        let page = self
+            .collection
+            .page_db()
+            .get(&page_id)?
+            .map(SearchResult::try_from)
+            .transpose()?;
 
         if page.is_none() {
             return Err(Error::BadIndexRecord);
@@ -167,33 +274,596 @@ impl Index {
         Ok(Some((page_id, page.unwrap())))
     }
 
+    /// Find a page by its url, along with its id.
+    pub fn find_url(&self, url: &str) -> Result<Option<(u64, SearchResult)>, Error> {
+        match self.collection.page_url_db().get(url.as_bytes())? {
+            Some(page_id) => {
+                let id = decode_id(&page_id)?;
+                Ok(self.lookup_id(id)?.map(|page| (id, page)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Mark a page as indexed now without changing anything else about it, for when it hasn't changed since it was
+    /// last indexed. Returns false if the url was not in the index.
+    pub fn touch_page(&self, url: &str) -> Result<bool, Error> {
+        let (id, mut page) = match self.find_url(url)? {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        page.last_index = time::OffsetDateTime::now_utc();
+        let page_data: IVec = page.into();
+        self.collection
+            .page_db()
+            .insert(id.to_be_bytes(), page_data)?;
+        self.collection.changed();
+        Ok(true)
+    }
+
+    /// The validators the crawler saved for url, if it saved any
+    pub fn validators(&self, url: &str) -> Result<Option<Vec<u8>>, Error> {
+        validators::load(&self.collection, url)
+    }
+
+    /// Save the validators url was served with, or forget them if there are none. They are forgotten too when the
+    /// page at url, or any page from the ingester, is deleted.
+    pub fn save_validators(
+        &self,
+        url: &str,
+        ingester: &str,
+        value: Option<&[u8]>,
+    ) -> Result<(), Error> {
+        match value {
+            Some(value) => validators::save(&self.collection, url, ingester, value),
+            None => validators::remove(&self.collection, url),
+        }
+    }
+
     pub fn lookup_id(&self, id: u64) -> Result<Option<SearchResult>, Error> {
-        let page: Option<SearchResult> = page_db().get(id.to_be_bytes())?.map(SearchResult::from);
+        let page: Option<SearchResult> = self
+            .collection
+            .page_db()
+            .get(id.to_be_bytes())?
+            .map(SearchResult::try_from)
+            .transpose()?;
         Ok(page)
     }
 
     pub fn store_page(&self, page: &Page) -> Result<(IVec, SearchResult), Error> {
         let search_result: SearchResult = page.into();
-        let id: IVec = (&page_db().generate_id()?.to_be_bytes()).into();
+        let id = self.store_search_result(&search_result)?;
 
-        page_url_db().insert(page.url.to_string().bytes().collect::<Vec<_>>(), id.clone())?;
+        Ok((id, search_result))
+    }
+
+    fn store_search_result(&self, search_result: &SearchResult) -> Result<IVec, Error> {
+        let id: IVec = (&self.collection.page_db().generate_id()?.to_be_bytes()).into();
+
+        self.collection
+            .page_url_db()
+            .insert(search_result.url.as_bytes(), id.clone())?;
 
         let page_data: IVec = search_result.clone().into();
-        page_db().insert(id.clone(), page_data)?;
+        self.collection.page_db().insert(id.clone(), page_data)?;
+        stats::page_added(&self.collection, search_result)?;
+        self.collection.changed();
 
-        Ok((id, search_result))
+        Ok(id)
     }
 
     fn store_words(&self, page_id: IVec, words: Vec<(String, u64)>) -> Result<(), Error> {
-        let mut batch = sled::Batch::default();
-        for (word, count) in words.into_iter() {
-            let mut key: Vec<u8> = word.as_bytes().to_vec();
-            key.push(WORD_KEY_SEPARATOR);
-            key.append(&mut page_id.as_ref().to_vec());
-            let value: IVec = (&count.to_be_bytes()).into();
-            batch.insert(key, value);
-        }
-        word_db().apply_batch(batch)?;
+        let mut page_words: Vec<u8> = Vec::new();
+        for (word, _) in words.iter() {
+            if !page_words.is_empty() {
+                page_words.push(PAGE_WORDS_SEPARATOR);
+            }
+            page_words.extend_from_slice(word.as_bytes());
+        }
+
+        let new_terms = postings::add_page(&self.collection, decode_id(&page_id)?, &words)?;
+        self.collection
+            .page_words_db()
+            .insert(page_id, page_words)?;
+        stats::postings_changed(&self.collection, words.len() as i64, new_terms as i64)?;
+        self.collection.changed();
         Ok(())
     }
+
+    /// Remove a page, its url mapping and all of its words from the index.
+    /// Returns false if the url was not in the index.
+    pub fn delete_page(&self, url: &str) -> Result<bool, Error> {
+        let page_id = self.collection.page_url_db().get(url.as_bytes())?;
+        match page_id {
+            Some(id) => {
+                self.delete_id(&id, url)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Remove every page with a url on the given host. Returns the number of pages removed.
+    pub fn delete_by_host(&self, host: &str) -> Result<usize, Error> {
+        let mut targets = Vec::new();
+        for row in self.collection.page_url_db().iter() {
+            let (key, id) = row?;
+            let url = String::from_utf8(key.to_vec())?;
+            let matches = match Url::parse(&url) {
+                Ok(u) => u.host_str() == Some(host),
+                Err(e) => {
+                    warn!("Could not parse url {} from the index: {}", url, e);
+                    false
+                }
+            };
+            if matches {
+                targets.push((id, url));
+            }
+        }
+
+        for (id, url) in targets.iter() {
+            self.delete_id(id, url)?;
+        }
+        validators::remove_matching(&self.collection, |url, _| {
+            Url::parse(url).is_ok_and(|u| u.host_str() == Some(host))
+        })?;
+        Ok(targets.len())
+    }
+
+    /// Remove every page that was added by the named ingester. Returns the number of pages removed.
+    ///
+    /// Pages indexed before we kept track of which ingester found them don't have one. If legacy_host is given, the
+    /// host the ingester fetches from, those on that host are removed as well.
+    pub fn delete_by_ingester(
+        &self,
+        ingester: &str,
+        legacy_host: Option<&str>,
+    ) -> Result<usize, Error> {
+        let mut targets = Vec::new();
+        for row in self.collection.page_db().iter() {
+            let (id, value) = row?;
+            let search_result = SearchResult::try_from(value)?;
+            let matches = if search_result.ingester.is_empty() {
+                legacy_host.is_some_and(|host| {
+                    Url::parse(&search_result.url).is_ok_and(|u| u.host_str() == Some(host))
+                })
+            } else {
+                search_result.ingester == ingester
+            };
+            if matches {
+                targets.push((id, search_result.url));
+            }
+        }
+
+        for (id, url) in targets.iter() {
+            self.delete_id(id, url)?;
+        }
+        // the ingester's feeds and sitemaps too, or they would say nothing has changed and the pages wouldn't come back
+        validators::remove_matching(&self.collection, |_, saved_by| saved_by == ingester)?;
+        Ok(targets.len())
+    }
+
+    fn delete_id(&self, page_id: &IVec, url: &str) -> Result<(), Error> {
+        debug!("deleting {} from the index", url);
+        self.delete_words(page_id)?;
+        documents::remove(&self.collection, page_id)?;
+        links::remove(&self.collection, page_id)?;
+        clicks::remove(&self.collection, page_id)?;
+        validators::remove(&self.collection, url)?;
+        self.collection.page_url_db().remove(url.as_bytes())?;
+        if let Some(page) = self.collection.page_db().remove(page_id)? {
+            stats::page_removed(&self.collection, &SearchResult::try_from(page)?)?;
+        }
+        self.collection.changed();
+        Ok(())
+    }
+
+    fn delete_words(&self, page_id: &IVec) -> Result<(), Error> {
+        let page_words = match self.collection.page_words_db().remove(page_id)? {
+            Some(w) => w,
+            None => {
+                warn!(
+                    "No word list for page {:?}, it has no words to remove",
+                    page_id
+                );
+                return Ok(());
+            }
+        };
+        let words: Vec<&[u8]> = page_words
+            .split(|b| *b == PAGE_WORDS_SEPARATOR)
+            .filter(|w| !w.is_empty())
+            .collect();
+
+        let (removed, removed_terms) =
+            postings::remove_page(&self.collection, decode_id(page_id)?, &words)?;
+        stats::postings_changed(&self.collection, -(removed as i64), -(removed_terms as i64))?;
+        self.collection.changed();
+        Ok(())
+    }
+
+    /// Throw away the word index and build it again from the stored text of each page, using the current rules for
+    /// splitting text into words. Pages without stored text are indexed from their title and description.
+    /// Returns the number of pages reindexed.
+    pub fn reindex(&self) -> Result<usize, Error> {
+        let total = self.collection.page_db().len();
+        info!("Reindexing {total} pages");
+
+        stats::invalidate(&self.collection)?;
+        self.collection.postings_db().clear()?;
+        self.collection.terms_db().clear()?;
+        self.collection.page_words_db().clear()?;
+
+        let mut count = 0;
+        let mut without_text = 0;
+        for row in self.collection.page_db().iter() {
+            let (id, value) = row?;
+            let page = SearchResult::try_from(value)?;
+
+            let words = match documents::load(&self.collection, &id)? {
+                Some(text) => word_counts(&page.title, &text),
+                None => {
+                    without_text += 1;
+                    word_counts(&page.title, &page.description)
+                }
+            };
+            self.store_words(id, words)?;
+
+            count += 1;
+            if count % PROGRESS_INTERVAL == 0 {
+                info!(
+                    "Reindexed {count} of {total} pages ({:.1}%)",
+                    percentage(total, count)
+                );
+            }
+        }
+
+        if without_text > 0 {
+            warn!(
+                "{without_text} pages had no stored text and were indexed from their description"
+            );
+        }
+        stats::ensure_counted(&self.collection)?;
+
+        Ok(count)
+    }
+
+    /// Record that a page was opened from the results of a search, so it ranks higher in future. Returns the page,
+    /// or None if there is no page with that id.
+    pub fn record_click(&self, id: u64, query: &str) -> Result<Option<SearchResult>, Error> {
+        let page = self.lookup_id(id)?;
+        if page.is_some() {
+            clicks::record(&self.collection, &id.to_be_bytes(), &normalise_query(query))?;
+            self.collection.changed();
+        }
+        Ok(page)
+    }
+
+    /// Work out how well linked to each page is, so better connected pages rank higher in searches. This only does
+    /// anything if links have been added or removed since it was last done, unless force is set.
+    /// Returns the number of pages ranked.
+    pub fn update_ranks(&self, force: bool) -> Result<usize, Error> {
+        links::update_ranks(&self.collection, force)
+    }
+
+    /// Make sure everything written to the collection is on disk. sled does this periodically on its own, but short
+    /// lived processes can exit before that happens.
+    pub fn flush(&self) -> Result<(), Error> {
+        for (_, db) in self.collection.trees() {
+            db.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Get the current size of the collection.
+    pub fn stats(&self) -> Result<IndexStats, Error> {
+        stats::ensure_counted(&self.collection)?;
+
+        let mut disk_usage = BTreeMap::new();
+        for (name, db) in self.collection.trees() {
+            disk_usage.insert(name.to_string(), db.size_on_disk()?);
+        }
+
+        stats::read_stats(&self.collection, disk_usage)
+    }
+}
+
+/// Search several collections at once, merging the results by score. Preferences are applied to the merged results,
+/// so pinned pages come first whichever collection they are in.
+pub async fn search_all(
+    indexes: &[Index],
+    search_string: &str,
+    preferences: &Preferences,
+) -> Result<Vec<SearchHit>, Error> {
+    let words = tokenise(search_string);
+    let query = normalise_query(search_string);
+    info!(
+        "Searching {} collections for matches to: {words:?}",
+        indexes.len()
+    );
+
+    let mut result = Vec::new();
+    for url in preferences.pins(search_string).iter() {
+        match find_pinned(indexes, url)? {
+            Some(hit) => result.push(hit),
+            None => info!("Pinned url {url} is not in the index"),
+        }
+    }
+
+    let mut scores = Vec::new();
+    for index in indexes.iter() {
+        for (id, score) in index.score_pages(&words, &query)? {
+            scores.push((score, index, id));
+        }
+    }
+    scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+    // domain rules can move pages from anywhere in the candidates into the results, so they all need looking at
+    if !preferences.has_domain_rules() {
+        scores.truncate(MAX_RESULTS + result.len());
+    }
+
+    let mut found = Vec::new();
+    for (score, index, id) in scores.into_iter() {
+        if result
+            .iter()
+            .any(|hit| hit.id == id && hit.collection == index.collection.name)
+        {
+            continue;
+        }
+        match index.lookup_id(id)? {
+            Some(page) => {
+                if preferences.is_blocked(&page.url) {
+                    continue;
+                }
+                let score = score * preferences.boost_for(&page.url);
+                found.push((
+                    score,
+                    SearchHit {
+                        id,
+                        collection: index.collection.name.clone(),
+                        page,
+                    },
+                ));
+            }
+            None => warn!(
+                "Search result returned id {} which doesn't have a page entry in {}. Index is broke!",
+                id, index.collection.name
+            ),
+        }
+    }
+    found.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    result.extend(found.into_iter().map(|(_, hit)| hit));
+    result.truncate(MAX_RESULTS);
+    info!("finished looking up details for {} pages", result.len());
+    Ok(result)
+}
+
+/// The pages that match any of the words in a search that were first indexed most recently, newest first. Unlike
+/// search_all this looks at every matching page, however well it matches, so a new page always makes it in.
+pub async fn newest_all(
+    indexes: &[Index],
+    search_string: &str,
+    preferences: &Preferences,
+    count: usize,
+) -> Result<Vec<SearchHit>, Error> {
+    let words = tokenise(search_string);
+    info!(
+        "Finding the newest pages in {} collections matching: {words:?}",
+        indexes.len()
+    );
+
+    let mut result = Vec::new();
+    for index in indexes.iter() {
+        for id in index.matching_pages(&words)? {
+            match index.lookup_id(id)? {
+                Some(page) => {
+                    if preferences.is_blocked(&page.url) {
+                        continue;
+                    }
+                    result.push(SearchHit {
+                        id,
+                        collection: index.collection.name.clone(),
+                        page,
+                    });
+                }
+                None => warn!(
+                    "Postings have id {} which doesn't have a page entry in {}. Index is broke!",
+                    id, index.collection.name
+                ),
+            }
+            // only keep the newest as we go, so a common word doesn't mean holding every page in memory
+            if result.len() > count * 2 {
+                sort_newest_first(&mut result);
+                result.truncate(count);
+            }
+        }
+    }
+    sort_newest_first(&mut result);
+    result.truncate(count);
+    Ok(result)
+}
+
+/// Newest first, with the later page id first for pages indexed at the same time.
+fn sort_newest_first(hits: &mut [SearchHit]) {
+    hits.sort_by_key(|hit| std::cmp::Reverse((hit.page.first_indexed(), hit.id)));
+}
+
+/// The first collection that has a page at a pinned url
+fn find_pinned(indexes: &[Index], url: &str) -> Result<Option<SearchHit>, Error> {
+    for index in indexes.iter() {
+        if let Some((id, page)) = index.find_url(url)? {
+            return Ok(Some(SearchHit {
+                id,
+                collection: index.collection.name.clone(),
+                page,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+/// Break up the title and content of a page into the words we want to index, and how often they appear.
+fn word_counts(title: &str, content: &str) -> Vec<(String, u64)> {
+    let mut words = tokenise(title);
+    words.append(&mut tokenise(content));
+    count_words(filter(words))
+}
+
+/// Page ids are stored as big endian u64s so they sort in the order they were created.
+fn decode_id(id: &[u8]) -> Result<u64, Error> {
+    Ok(u64::from_be_bytes(
+        id.try_into().map_err(|_| Error::BadIndexRecord)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::Page;
+    use crate::index_sled::Index;
+
+    fn page(url: &str, content: &str, ingester: &str, links: &[&str]) -> Page {
+        Page {
+            url: url::Url::parse(url).unwrap(),
+            title: url.to_string(),
+            content: content.to_string(),
+            ingester: ingester.to_string(),
+            links: links.iter().map(|l| url::Url::parse(l).unwrap()).collect(),
+        }
+    }
+
+    async fn add(index: &Index, page: &Page) {
+        index
+            .add_page(page, time::Duration::ZERO, usize::MAX)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_page() {
+        let index = Index::temporary().unwrap();
+        add(&index, &page("https://a.example/", "apples", "test", &[])).await;
+        add(
+            &index,
+            &page("https://b.example/", "apples pears", "test", &[]),
+        )
+        .await;
+        let (id, _) = index.find_url("https://a.example/").unwrap().unwrap();
+        let page_id = id.to_be_bytes();
+
+        assert!(index.delete_page("https://a.example/").unwrap());
+        assert!(!index.delete_page("https://a.example/").unwrap());
+
+        let collection = &index.collection;
+        assert!(collection
+            .page_url_db()
+            .get("https://a.example/")
+            .unwrap()
+            .is_none());
+        assert!(collection.page_db().get(page_id).unwrap().is_none());
+        assert!(collection.page_words_db().get(page_id).unwrap().is_none());
+        assert!(collection.documents_db().get(page_id).unwrap().is_none());
+        // nothing left in the postings either
+        let (other, _) = index.find_url("https://b.example/").unwrap().unwrap();
+        let found: Vec<u64> = index
+            .score_pages(&["apples".to_string()], "apples")
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(found, vec![other]);
+        assert_eq!(index.stats().unwrap().pages, 1);
+    }
+
+    #[tokio::test]
+    async fn test_add_page_again() {
+        let index = Index::temporary().unwrap();
+        add(&index, &page("https://a.example/", "apples", "feed", &[])).await;
+        let (id, first) = index.find_url("https://a.example/").unwrap().unwrap();
+
+        let mut changed = page("https://a.example/", "pears", "sitemap", &[]);
+        changed.title = "Pears".to_string();
+        add(&index, &changed).await;
+
+        let (same_id, second) = index.find_url("https://a.example/").unwrap().unwrap();
+        assert_eq!(same_id, id);
+        assert_eq!(second.title, "Pears");
+        assert_eq!(second.description, "pears");
+        assert!(second.last_index >= first.last_index);
+        assert_eq!(second.first_index, first.first_index);
+        assert!(index.search("apples").await.unwrap().is_empty());
+        assert_eq!(index.search("pears").await.unwrap().len(), 1);
+
+        let stats = index.stats().unwrap();
+        assert_eq!(stats.pages, 1);
+        assert_eq!(stats.pages_per_ingester.get("feed"), None);
+        assert_eq!(stats.pages_per_ingester.get("sitemap"), Some(&1));
+    }
+
+    #[tokio::test]
+    async fn test_newest_all() {
+        let index = Index::temporary().unwrap();
+        for i in 0..5 {
+            let url = format!("https://a.example/{i}");
+            add(&index, &page(&url, "otter otter otter otter", "test", &[])).await;
+        }
+        // barely mentions otters, but is the newest
+        add(
+            &index,
+            &page("https://b.example/", "otter badger", "test", &[]),
+        )
+        .await;
+        add(&index, &page("https://c.example/", "badger", "test", &[])).await;
+
+        let preferences = crate::preferences::Preferences::default();
+        let newest = super::newest_all(std::slice::from_ref(&index), "otter", &preferences, 2)
+            .await
+            .unwrap();
+        let urls: Vec<&str> = newest.iter().map(|hit| hit.page.url.as_str()).collect();
+        assert_eq!(urls, ["https://b.example/", "https://a.example/4"]);
+    }
+
+    #[tokio::test]
+    async fn test_delete_by_ingester() {
+        let index = Index::temporary().unwrap();
+        add(&index, &page("https://a.example/1", "apples", "feed", &[])).await;
+        // indexed before we kept the ingester
+        add(&index, &page("https://a.example/2", "apples", "", &[])).await;
+        add(&index, &page("https://c.example/", "apples", "", &[])).await;
+        add(&index, &page("https://a.example/3", "apples", "other", &[])).await;
+
+        assert_eq!(index.delete_by_ingester("feed", None).unwrap(), 1);
+        assert_eq!(
+            index.delete_by_ingester("feed", Some("a.example")).unwrap(),
+            1
+        );
+        assert!(index.find_url("https://a.example/2").unwrap().is_none());
+        assert!(index.find_url("https://c.example/").unwrap().is_some());
+        assert!(index.find_url("https://a.example/3").unwrap().is_some());
+        assert_eq!(index.search("apples").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_update_ranks_changes_generation() {
+        let index = Index::temporary().unwrap();
+        add(
+            &index,
+            &page(
+                "https://a.example/",
+                "apples",
+                "test",
+                &["https://b.example/"],
+            ),
+        )
+        .await;
+        add(&index, &page("https://b.example/", "pears", "test", &[])).await;
+
+        let before = index.generation();
+        assert_eq!(index.update_ranks(false).unwrap(), 2);
+        assert!(index.generation() > before);
+
+        // nothing to do, so nothing changed
+        let before = index.generation();
+        assert_eq!(index.update_ranks(false).unwrap(), 0);
+        assert_eq!(index.generation(), before);
+    }
 }
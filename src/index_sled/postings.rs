@@ -0,0 +1,386 @@
+//! Storage for the inverted index of word to the pages it appears on.
+//!
+//! Each word has one or more blocks of postings. A block is stored under the key `word=<first page id>` and holds up
+//! to `MAX_BLOCK_SIZE` (page id, count) pairs sorted by page id. The page ids are delta encoded against the previous
+//! id in the block and everything is written as variable length integers, so a typical posting takes two or three
+//! bytes rather than the 24 or so a key per posting needs.
+//!
+//! Alongside the postings we keep an upper bound on the count of each word on any page, which lets searches skip
+//! pages that can't make it into the top results. The bound is raised as pages are added but not lowered when they
+//! are removed, it only has to be at least as big as the real maximum.
+
+use std::ops::Bound;
+
+use sled::IVec;
+
+use crate::error::Error;
+use crate::utils::varint;
+
+use super::collection::Collection;
+use super::top_k::Cursor;
+
+pub(super) const WORD_KEY_SEPARATOR: u8 = b'=';
+
+/// Blocks are split in two when they grow past this many postings.
+const MAX_BLOCK_SIZE: usize = 128;
+
+pub(super) type Posting = (u64, u64);
+
+pub(super) fn word_prefix(word: &[u8]) -> Vec<u8> {
+    let mut prefix = word.to_vec();
+    prefix.push(WORD_KEY_SEPARATOR);
+    prefix
+}
+
+/// First key after every block for a word
+fn word_end(word: &[u8]) -> Vec<u8> {
+    let mut end = word.to_vec();
+    end.push(WORD_KEY_SEPARATOR + 1);
+    end
+}
+
+fn block_key(word: &[u8], start: u64) -> Vec<u8> {
+    let mut key = word_prefix(word);
+    key.extend_from_slice(&start.to_be_bytes());
+    key
+}
+
+pub(super) fn encode_block(postings: &[Posting]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    varint::write_u64(&mut buffer, postings.len() as u64);
+    let mut previous = postings.first().map(|p| p.0).unwrap_or(0);
+    for (id, count) in postings.iter() {
+        varint::write_u64(&mut buffer, id - previous);
+        varint::write_u64(&mut buffer, *count);
+        previous = *id;
+    }
+    buffer
+}
+
+/// Decode a block. The key holds the id of the first posting, which the deltas are relative to.
+pub(super) fn decode_block(key: &[u8], value: &[u8]) -> Result<Vec<Posting>, Error> {
+    let start = key
+        .len()
+        .checked_sub(8)
+        .and_then(|i| key[i..].try_into().ok())
+        .map(u64::from_be_bytes)
+        .ok_or(Error::BadIndexRecord)?;
+
+    let mut input = value;
+    let length = varint::read_u64(&mut input).ok_or(Error::BadIndexRecord)?;
+    let mut result = Vec::with_capacity(length.min(MAX_BLOCK_SIZE as u64 * 2) as usize);
+    let mut id = start;
+    for _ in 0..length {
+        let delta = varint::read_u64(&mut input).ok_or(Error::BadIndexRecord)?;
+        id = id.checked_add(delta).ok_or(Error::BadIndexRecord)?;
+        let count = varint::read_u64(&mut input).ok_or(Error::BadIndexRecord)?;
+        result.push((id, count));
+    }
+    Ok(result)
+}
+
+/// Number of postings in a block without decoding all of it.
+pub(super) fn block_length(value: &[u8]) -> u64 {
+    let mut input = value;
+    varint::read_u64(&mut input).unwrap_or(0)
+}
+
+/// Add the blocks needed to hold a sorted list of postings for a word to a batch.
+pub(super) fn write_blocks(batch: &mut sled::Batch, word: &[u8], postings: &[Posting]) {
+    for chunk in postings.chunks(MAX_BLOCK_SIZE) {
+        batch.insert(block_key(word, chunk[0].0), encode_block(chunk));
+    }
+}
+
+/// True if there are any pages for this word
+pub(super) fn term_exists(collection: &Collection, word: &[u8]) -> Result<bool, Error> {
+    Ok(collection
+        .postings_db()
+        .scan_prefix(word_prefix(word))
+        .next()
+        .transpose()?
+        .is_some())
+}
+
+/// Highest count the word could have on any page, or None if we don't know.
+pub(super) fn term_bound(collection: &Collection, word: &[u8]) -> Result<Option<u64>, Error> {
+    Ok(collection
+        .terms_db()
+        .get(word)?
+        .and_then(|v| varint::read_u64(&mut v.as_ref())))
+}
+
+pub(super) fn encode_bound(bound: u64) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    varint::write_u64(&mut buffer, bound);
+    buffer
+}
+
+/// Find the block that page id belongs in. That is the last block starting at or before the id, or the first block
+/// for the word if the id is before all of them.
+fn find_block(
+    collection: &Collection,
+    word: &[u8],
+    id: u64,
+) -> Result<Option<(IVec, Vec<Posting>)>, Error> {
+    let prefix = word_prefix(word);
+    let row = match collection
+        .postings_db()
+        .range(prefix.clone()..=block_key(word, id))
+        .next_back()
+    {
+        Some(row) => Some(row?),
+        None => collection
+            .postings_db()
+            .scan_prefix(prefix)
+            .next()
+            .transpose()?,
+    };
+
+    match row {
+        Some((key, value)) => {
+            let postings = decode_block(&key, &value)?;
+            Ok(Some((key, postings)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Look up the count for a word on a single page.
+pub(super) fn get(collection: &Collection, word: &[u8], id: u64) -> Result<Option<u64>, Error> {
+    Ok(find_block(collection, word, id)?.and_then(|(_, postings)| {
+        postings
+            .binary_search_by_key(&id, |p| p.0)
+            .ok()
+            .map(|i| postings[i].1)
+    }))
+}
+
+/// Record the words found on a page. Returns the number of words that were not in the index before.
+pub(super) fn add_page(
+    collection: &Collection,
+    id: u64,
+    words: &[(String, u64)],
+) -> Result<u64, Error> {
+    let _guard = collection
+        .postings_lock
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    let mut new_terms = 0;
+    let mut batch = sled::Batch::default();
+    for (word, count) in words.iter() {
+        let word = word.as_bytes();
+        // a word has a bound for as long as it has postings, so raising it in one step also tells us if it is new
+        // without anything else being able to count it too
+        let old_bound = collection.terms_db().fetch_and_update(word, |old| {
+            let bound = old.and_then(|mut v| varint::read_u64(&mut v)).unwrap_or(0);
+            Some(encode_bound(bound.max(*count)))
+        })?;
+        if old_bound.is_none() {
+            new_terms += 1;
+        }
+
+        let (old_key, mut postings) = match find_block(collection, word, id)? {
+            Some((key, postings)) => (Some(key), postings),
+            None => (None, Vec::new()),
+        };
+
+        match postings.binary_search_by_key(&id, |p| p.0) {
+            Ok(i) => postings[i].1 = *count,
+            Err(i) => postings.insert(i, (id, *count)),
+        }
+
+        if let Some(key) = old_key {
+            batch.remove(key);
+        }
+        if postings.len() > MAX_BLOCK_SIZE {
+            let (first, second) = postings.split_at(postings.len() / 2);
+            write_blocks(&mut batch, word, first);
+            write_blocks(&mut batch, word, second);
+        } else {
+            write_blocks(&mut batch, word, &postings);
+        }
+    }
+    collection.postings_db().apply_batch(batch)?;
+
+    Ok(new_terms)
+}
+
+/// Remove a page from the postings of the given words. Returns the number of postings removed and the number of
+/// words that no longer have any pages.
+pub(super) fn remove_page(
+    collection: &Collection,
+    id: u64,
+    words: &[&[u8]],
+) -> Result<(u64, u64), Error> {
+    let _guard = collection
+        .postings_lock
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    let mut removed = 0;
+    let mut batch = sled::Batch::default();
+    for word in words.iter() {
+        if let Some((key, mut postings)) = find_block(collection, word, id)? {
+            if let Ok(i) = postings.binary_search_by_key(&id, |p| p.0) {
+                postings.remove(i);
+                removed += 1;
+                batch.remove(key);
+                write_blocks(&mut batch, word, &postings);
+            }
+        }
+    }
+    collection.postings_db().apply_batch(batch)?;
+
+    let mut removed_terms = 0;
+    for word in words.iter() {
+        // only whoever actually removes the bound counts the word as gone
+        if !term_exists(collection, word)? && collection.terms_db().remove(word)?.is_some() {
+            removed_terms += 1;
+        }
+    }
+
+    Ok((removed, removed_terms))
+}
+
+/// Walks through the postings for a word in page id order, reading one block at a time so only a single block is
+/// ever held in memory.
+pub(super) struct PostingsCursor<'a> {
+    db: &'a sled::Db,
+    word: Vec<u8>,
+    blocks: sled::Iter,
+    block: Vec<Posting>,
+    position: usize,
+    bound: u64,
+}
+
+impl<'a> PostingsCursor<'a> {
+    pub(super) fn new(collection: &'a Collection, word: &[u8]) -> Result<Self, Error> {
+        let db = collection.postings_db();
+        let mut cursor = PostingsCursor {
+            db,
+            word: word.to_vec(),
+            blocks: db.range(word_prefix(word)..word_end(word)),
+            block: Vec::new(),
+            position: 0,
+            // without a stored bound assume the word could score anything, so it is never skipped
+            bound: term_bound(collection, word)?.unwrap_or(u64::MAX),
+        };
+        cursor.next_block()?;
+        Ok(cursor)
+    }
+
+    fn next_block(&mut self) -> Result<(), Error> {
+        self.block.clear();
+        self.position = 0;
+        for row in self.blocks.by_ref() {
+            let (key, value) = row?;
+            self.block = decode_block(&key, &value)?;
+            if !self.block.is_empty() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Cursor for PostingsCursor<'_> {
+    fn current(&self) -> Option<Posting> {
+        self.block.get(self.position).copied()
+    }
+
+    fn advance(&mut self) -> Result<(), Error> {
+        self.position += 1;
+        if self.position >= self.block.len() {
+            self.next_block()?;
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, id: u64) -> Result<(), Error> {
+        // if the id is past this block jump straight to the block it would be in, rather than reading every block
+        // between here and there
+        if let Some(last) = self.block.last() {
+            if last.0 < id {
+                let current_key = block_key(&self.word, self.block[0].0);
+                let target = block_key(&self.word, id);
+                if let Some(row) = self.db.range(word_prefix(&self.word)..=target).next_back() {
+                    let (key, value) = row?;
+                    if key.as_ref() > current_key.as_slice() {
+                        self.block = decode_block(&key, &value)?;
+                        self.position = 0;
+                        self.blocks = self.db.range::<&[u8], _>((
+                            Bound::Excluded(key.as_ref()),
+                            Bound::Excluded(word_end(&self.word).as_slice()),
+                        ));
+                    }
+                }
+            }
+        }
+
+        loop {
+            match self.block.last() {
+                None => return Ok(()),
+                Some(last) if last.0 >= id => {
+                    self.position += self.block[self.position..].partition_point(|p| p.0 < id);
+                    return Ok(());
+                }
+                Some(_) => self.next_block()?,
+            }
+        }
+    }
+
+    fn upper_bound(&self) -> u64 {
+        self.bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::index_sled::postings::block_key;
+    use crate::index_sled::postings::decode_block;
+    use crate::index_sled::postings::encode_block;
+    use crate::index_sled::postings::Posting;
+    use crate::index_sled::postings::MAX_BLOCK_SIZE;
+
+    #[test]
+    fn test_block_round_trip() {
+        let postings: Vec<Posting> = vec![(5, 1), (6, 12), (300, 2), (70_000, 1), (u64::MAX, 3)];
+        let key = block_key(b"word", 5);
+        let decoded = decode_block(&key, &encode_block(&postings)).unwrap();
+        assert_eq!(decoded, postings);
+    }
+
+    #[test]
+    fn test_bad_block() {
+        let postings: Vec<Posting> = vec![(5, 1), (6, 12)];
+        let key = block_key(b"word", 5);
+        let encoded = encode_block(&postings);
+        assert!(decode_block(&key, &encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_block(b"short", &encoded).is_err());
+
+        // a delta that would take the id past the largest there can be
+        let key = block_key(b"word", u64::MAX);
+        assert!(decode_block(&key, &encoded).is_err());
+    }
+
+    /// Compare the space used by this layout with the previous one of a key per posting (`word=<8 byte id>`) holding
+    /// an 8 byte count. Run with `cargo test layout_size -- --nocapture` to see the numbers.
+    #[test]
+    fn test_layout_size() {
+        let word = b"history";
+        // pages added in id order with a few gaps, which is what the crawler produces
+        let postings: Vec<Posting> = (0..10_000_u64).map(|i| (1000 + i * 3, 1 + i % 7)).collect();
+
+        let old_size: usize = postings.len() * (word.len() + 1 + 8 + 8);
+        let new_size: usize = postings
+            .chunks(MAX_BLOCK_SIZE)
+            .map(|chunk| block_key(word, chunk[0].0).len() + encode_block(chunk).len())
+            .sum();
+
+        assert!(new_size * 5 < old_size);
+        // small gaps and counts take a byte each, so with the block keys that is under three bytes a posting
+        assert!(new_size < postings.len() * 3, "{new_size} bytes");
+    }
+}
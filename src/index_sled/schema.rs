@@ -0,0 +1,339 @@
+//! Keeps track of which version of the storage layout the index on disk uses, and upgrades older indexes when we
+//! change it.
+//!
+//! To change the layout bump `SCHEMA_VERSION` and add a function to `MIGRATIONS` that takes an index from the
+//! previous version to the new one.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use log::info;
+use sled::IVec;
+
+use crate::data::SearchResult;
+use crate::error::Error;
+
+use super::collection::Collection;
+use super::decode_id;
+use super::postings::decode_block;
+use super::postings::encode_bound;
+use super::postings::write_blocks;
+use super::postings::Posting;
+use super::postings::WORD_KEY_SEPARATOR;
+use super::PAGE_WORDS_SEPARATOR;
+
+const SCHEMA_VERSION_KEY: &[u8] = b"schema_version";
+
+/// The version of the layout this build of ceridwen writes.
+pub(super) const SCHEMA_VERSION: u32 = 4;
+
+/// How many times, and how often, to try opening a legacy tree that is still locked
+const LOCK_RETRIES: u32 = 50;
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+type Migration = fn(&Collection) -> Result<(), Error>;
+
+/// Migrations in order. Entry n takes an index from version n + 1 to version n + 2.
+const MIGRATIONS: &[Migration] = &[build_page_words, compact_encoding, build_term_bounds];
+
+/// Upgrade a collection on disk to the current schema version if needed. This is called once when the collection is
+/// opened.
+pub(super) fn ensure_current(collection: &Collection) -> Result<(), Error> {
+    let mut version = stored_version(collection)?;
+    // versions start at 1, so 0 can only come from a broken or foreign meta tree
+    if version == 0 {
+        return Err(Error::BadIndexRecord);
+    }
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchema(version, SCHEMA_VERSION));
+    }
+
+    while version < SCHEMA_VERSION {
+        info!(
+            "Upgrading collection {} from version {} to {}",
+            collection.name,
+            version,
+            version + 1
+        );
+        MIGRATIONS[(version - 1) as usize](collection)?;
+        version += 1;
+        set_version(collection, version)?;
+    }
+
+    Ok(())
+}
+
+fn stored_version(collection: &Collection) -> Result<u32, Error> {
+    match collection.meta_db().get(SCHEMA_VERSION_KEY)? {
+        Some(v) => Ok(u32::from_be_bytes(
+            v[..].try_into().map_err(|_| Error::BadIndexRecord)?,
+        )),
+        // Indexes from before we kept a version are version 1. A brand new index doesn't need upgrading.
+        None if collection.page_db().is_empty() => {
+            set_version(collection, SCHEMA_VERSION)?;
+            Ok(SCHEMA_VERSION)
+        }
+        None => Ok(1),
+    }
+}
+
+fn set_version(collection: &Collection, version: u32) -> Result<(), Error> {
+    collection
+        .meta_db()
+        .insert(SCHEMA_VERSION_KEY, &version.to_be_bytes())?;
+    collection.meta_db().flush()?;
+    Ok(())
+}
+
+/// Before version 3 postings were stored with one key per posting (`word=<8 byte page id>`) holding an 8 byte count
+fn legacy_word_db_path(collection: &Collection) -> PathBuf {
+    collection.path.join("word_index")
+}
+
+/// Open the word index from before version 3. sled's background threads can keep it locked for a moment after the
+/// last migration finished with it, so give them a chance to let go.
+fn open_legacy_word_db(collection: &Collection) -> Result<sled::Db, Error> {
+    let path = legacy_word_db_path(collection);
+    let mut attempts = 0;
+    loop {
+        match sled::open(&path) {
+            Ok(db) => return Ok(db),
+            Err(sled::Error::Io(e))
+                if e.kind() == io::ErrorKind::Other && attempts < LOCK_RETRIES =>
+            {
+                attempts += 1;
+                thread::sleep(LOCK_RETRY_DELAY);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Version 2 keeps a list of words for each page so the page can be removed without scanning the whole word index.
+/// Build those lists for every page that doesn't have one.
+fn build_page_words(collection: &Collection) -> Result<(), Error> {
+    let word_db = open_legacy_word_db(collection)?;
+
+    let mut missing: HashSet<Vec<u8>> = HashSet::new();
+    for row in collection.page_db().iter() {
+        let (id, _) = row?;
+        if !collection.page_words_db().contains_key(&id)? {
+            missing.insert(id.to_vec());
+        }
+    }
+    info!("Building word lists for {} pages", missing.len());
+
+    for row in word_db.iter() {
+        let (key, _) = row?;
+        let separator = match key.iter().position(|b| *b == WORD_KEY_SEPARATOR) {
+            Some(i) => i,
+            None => continue,
+        };
+        let (word, id) = (&key[..separator], &key[separator + 1..]);
+        if !missing.contains(id) {
+            continue;
+        }
+
+        collection.page_words_db().update_and_fetch(id, |old| {
+            let mut words = old.map(|o| o.to_vec()).unwrap_or_default();
+            if !words.is_empty() {
+                words.push(PAGE_WORDS_SEPARATOR);
+            }
+            words.extend_from_slice(word);
+            Some(words)
+        })?;
+    }
+
+    collection.page_words_db().flush()?;
+    Ok(())
+}
+
+/// Version 3 stores page records in a binary format and postings in delta encoded blocks. Rewrite any json page
+/// records and move the postings over from the old word index.
+fn compact_encoding(collection: &Collection) -> Result<(), Error> {
+    info!("Converting page records");
+    for row in collection.page_db().iter() {
+        let (id, value) = row?;
+        if value.first() == Some(&b'{') {
+            let page = SearchResult::try_from(value)?;
+            collection.page_db().insert(id, IVec::from(page))?;
+        }
+    }
+    collection.page_db().flush()?;
+
+    let legacy_path = legacy_word_db_path(collection);
+    if !legacy_path.exists() {
+        return Ok(());
+    }
+
+    info!("Converting postings");
+    // start from scratch in case a previous attempt was interrupted
+    collection.postings_db().clear()?;
+    let word_db = open_legacy_word_db(collection)?;
+
+    let mut word: Vec<u8> = Vec::new();
+    let mut word_postings: Vec<Posting> = Vec::new();
+    let mut batch = sled::Batch::default();
+    for row in word_db.iter() {
+        let (key, value) = row?;
+        let separator = match key.iter().position(|b| *b == WORD_KEY_SEPARATOR) {
+            Some(i) => i,
+            None => continue,
+        };
+        if key[..separator] != word[..] {
+            write_blocks(&mut batch, &word, &word_postings);
+            collection.postings_db().apply_batch(batch)?;
+            batch = sled::Batch::default();
+            word = key[..separator].to_vec();
+            word_postings.clear();
+        }
+
+        let id = decode_id(&key[separator + 1..])?;
+        let count = u64::from_be_bytes(value[..].try_into().map_err(|_| Error::BadIndexRecord)?);
+        word_postings.push((id, count));
+    }
+    write_blocks(&mut batch, &word, &word_postings);
+    collection.postings_db().apply_batch(batch)?;
+    collection.postings_db().flush()?;
+
+    drop(word_db);
+    fs::remove_dir_all(legacy_path)?;
+    Ok(())
+}
+
+/// Version 4 keeps the highest count of each word so searches can skip pages that can't make the top results.
+fn build_term_bounds(collection: &Collection) -> Result<(), Error> {
+    info!("Finding the highest count for each word");
+    collection.terms_db().clear()?;
+
+    let mut word: Vec<u8> = Vec::new();
+    let mut bound = 0;
+    for row in collection.postings_db().iter() {
+        let (key, value) = row?;
+        let separator = match key.iter().position(|b| *b == WORD_KEY_SEPARATOR) {
+            Some(i) => i,
+            None => continue,
+        };
+        if key[..separator] != word[..] {
+            if !word.is_empty() {
+                collection.terms_db().insert(&word, encode_bound(bound))?;
+            }
+            word = key[..separator].to_vec();
+            bound = 0;
+        }
+        for (_, count) in decode_block(&key, &value)? {
+            bound = bound.max(count);
+        }
+    }
+    if !word.is_empty() {
+        collection.terms_db().insert(&word, encode_bound(bound))?;
+    }
+
+    collection.terms_db().flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sled::IVec;
+
+    use crate::data::SearchResult;
+    use crate::error::Error;
+    use crate::index_sled::collection::Collection;
+    use crate::index_sled::postings::decode_block;
+    use crate::index_sled::postings::encode_bound;
+    use crate::index_sled::postings::term_bound;
+    use crate::index_sled::postings::word_prefix;
+    use crate::index_sled::schema::ensure_current;
+    use crate::index_sled::schema::legacy_word_db_path;
+    use crate::index_sled::schema::set_version;
+    use crate::index_sled::schema::stored_version;
+    use crate::index_sled::schema::SCHEMA_VERSION;
+
+    #[test]
+    fn test_upgrade_from_first_version() {
+        let collection = Collection::temporary("schema").unwrap();
+
+        // an index from before we kept a version: json page records and a key per posting
+        let id = 7u64.to_be_bytes();
+        let page = SearchResult {
+            url: "https://example.com/".to_string(),
+            title: "Apples".to_string(),
+            description: "apples and pears".to_string(),
+            last_index: time::macros::datetime!(2024-02-02 09:03:51 UTC),
+            ingester: String::new(),
+            first_index: None,
+        };
+        collection
+            .page_db()
+            .insert(id, serde_json::to_vec(&page).unwrap())
+            .unwrap();
+        let word_db = sled::open(legacy_word_db_path(&collection)).unwrap();
+        for (word, count) in [("apples", 2u64), ("pears", 1)] {
+            let mut key = word.as_bytes().to_vec();
+            key.push(b'=');
+            key.extend_from_slice(&id);
+            word_db.insert(key, &count.to_be_bytes()).unwrap();
+        }
+        drop(word_db);
+        assert_eq!(stored_version(&collection).unwrap(), 1);
+
+        ensure_current(&collection).unwrap();
+
+        assert_eq!(stored_version(&collection).unwrap(), SCHEMA_VERSION);
+        // version 2 built the word list
+        assert_eq!(
+            collection.page_words_db().get(id).unwrap().unwrap(),
+            IVec::from("apples pears")
+        );
+        // version 3 rewrote the page record and moved the postings over
+        let record = collection.page_db().get(id).unwrap().unwrap();
+        assert_ne!(record.first(), Some(&b'{'));
+        assert_eq!(SearchResult::try_from(record).unwrap().title, "Apples");
+        let (key, value) = collection
+            .postings_db()
+            .scan_prefix(word_prefix(b"apples"))
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(decode_block(&key, &value).unwrap(), vec![(7, 2)]);
+        assert!(!legacy_word_db_path(&collection).exists());
+        // version 4 found the highest counts
+        assert_eq!(term_bound(&collection, b"apples").unwrap(), Some(2));
+        assert_eq!(
+            collection.terms_db().get("pears").unwrap().unwrap(),
+            IVec::from(encode_bound(1))
+        );
+    }
+
+    #[test]
+    fn test_new_collection_is_current() {
+        let collection = Collection::temporary("schema").unwrap();
+        ensure_current(&collection).unwrap();
+        assert_eq!(stored_version(&collection).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_newer_version_is_refused() {
+        let collection = Collection::temporary("schema").unwrap();
+        set_version(&collection, SCHEMA_VERSION + 1).unwrap();
+        assert!(matches!(
+            ensure_current(&collection),
+            Err(Error::UnsupportedSchema(_, SCHEMA_VERSION))
+        ));
+    }
+
+    #[test]
+    fn test_version_zero_is_refused() {
+        let collection = Collection::temporary("schema").unwrap();
+        set_version(&collection, 0).unwrap();
+        assert!(matches!(
+            ensure_current(&collection),
+            Err(Error::BadIndexRecord)
+        ));
+    }
+}
@@ -0,0 +1,257 @@
+//! Counters describing how big a collection is. These are kept up to date as pages are added and removed so we don't
+//! need to scan the whole index every time someone wants to look at them.
+
+use std::collections::BTreeMap;
+
+use log::info;
+use serde::Serialize;
+use url::Url;
+
+use crate::data::SearchResult;
+use crate::error::Error;
+
+use super::collection::Collection;
+use super::postings::block_length;
+use super::postings::WORD_KEY_SEPARATOR;
+
+const PAGES_KEY: &[u8] = b"pages";
+const TERMS_KEY: &[u8] = b"terms";
+const POSTINGS_KEY: &[u8] = b"postings";
+const INGESTER_PREFIX: &[u8] = b"ingester=";
+const HOST_PREFIX: &[u8] = b"host=";
+
+/// Set once the counters have been built from a full scan of the index. Indexes created before we kept counters
+/// won't have this.
+const COUNTED_KEY: &[u8] = b"counted";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexStats {
+    pub collection: String,
+    pub pages: u64,
+    /// number of distinct words in the index
+    pub terms: u64,
+    /// number of word to page entries in the index
+    pub postings: u64,
+    /// bytes on disk used by each of the trees that make up the index
+    pub disk_usage: BTreeMap<String, u64>,
+    pub pages_per_ingester: BTreeMap<String, u64>,
+    pub pages_per_host: BTreeMap<String, u64>,
+}
+
+impl IndexStats {
+    pub fn total_disk_usage(&self) -> u64 {
+        self.disk_usage.values().sum()
+    }
+}
+
+pub(super) fn page_added(collection: &Collection, page: &SearchResult) -> Result<(), Error> {
+    page_changed(collection, page, 1)
+}
+
+pub(super) fn page_removed(collection: &Collection, page: &SearchResult) -> Result<(), Error> {
+    page_changed(collection, page, -1)
+}
+
+pub(super) fn postings_changed(
+    collection: &Collection,
+    postings: i64,
+    terms: i64,
+) -> Result<(), Error> {
+    add(collection, POSTINGS_KEY, postings)?;
+    add(collection, TERMS_KEY, terms)
+}
+
+fn page_changed(collection: &Collection, page: &SearchResult, delta: i64) -> Result<(), Error> {
+    add(collection, PAGES_KEY, delta)?;
+    add(
+        collection,
+        &prefixed(INGESTER_PREFIX, &page.ingester),
+        delta,
+    )?;
+    add(collection, &prefixed(HOST_PREFIX, &host(&page.url)), delta)
+}
+
+fn host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn prefixed(prefix: &[u8], name: &str) -> Vec<u8> {
+    let mut key = prefix.to_vec();
+    key.extend_from_slice(name.as_bytes());
+    key
+}
+
+fn decode(value: &[u8]) -> u64 {
+    value.try_into().map(u64::from_be_bytes).unwrap_or(0)
+}
+
+/// Add delta to a counter, removing it entirely if it drops to zero.
+fn add(collection: &Collection, key: &[u8], delta: i64) -> Result<(), Error> {
+    if delta == 0 {
+        return Ok(());
+    }
+    collection.stats_db().update_and_fetch(key, |old| {
+        let current = old.map(decode).unwrap_or(0);
+        let updated = current.saturating_add_signed(delta);
+        if updated == 0 {
+            None
+        } else {
+            Some(updated.to_be_bytes().to_vec())
+        }
+    })?;
+    Ok(())
+}
+
+/// Throw away the counters so they are rebuilt from scratch next time they are needed.
+pub(super) fn invalidate(collection: &Collection) -> Result<(), Error> {
+    collection.stats_db().remove(COUNTED_KEY)?;
+    Ok(())
+}
+
+/// Make sure the counters cover the whole index, rebuilding them with a full scan if they don't.
+pub(super) fn ensure_counted(collection: &Collection) -> Result<(), Error> {
+    if collection.stats_db().contains_key(COUNTED_KEY)? {
+        return Ok(());
+    }
+
+    info!(
+        "No statistics found for collection {}. Counting everything in it, this may take a while",
+        collection.name
+    );
+    let mut counters: BTreeMap<Vec<u8>, u64> = BTreeMap::new();
+
+    for row in collection.page_db().iter() {
+        let (_, value) = row?;
+        let page = SearchResult::try_from(value)?;
+        *counters.entry(PAGES_KEY.to_vec()).or_default() += 1;
+        *counters
+            .entry(prefixed(INGESTER_PREFIX, &page.ingester))
+            .or_default() += 1;
+        *counters
+            .entry(prefixed(HOST_PREFIX, &host(&page.url)))
+            .or_default() += 1;
+    }
+
+    // keys are sorted so all the entries for one word are next to each other
+    let mut last_word: Vec<u8> = Vec::new();
+    for row in collection.postings_db().iter() {
+        let (key, value) = row?;
+        *counters.entry(POSTINGS_KEY.to_vec()).or_default() += block_length(&value);
+        let word = match key.iter().position(|b| *b == WORD_KEY_SEPARATOR) {
+            Some(i) => &key[..i],
+            None => &key[..],
+        };
+        if word != last_word.as_slice() {
+            *counters.entry(TERMS_KEY.to_vec()).or_default() += 1;
+            last_word = word.to_vec();
+        }
+    }
+
+    collection.stats_db().clear()?;
+    let mut batch = sled::Batch::default();
+    for (key, value) in counters.into_iter() {
+        batch.insert(key, &value.to_be_bytes());
+    }
+    batch.insert(COUNTED_KEY, &[1]);
+    collection.stats_db().apply_batch(batch)?;
+    info!("Finished counting collection {}", collection.name);
+
+    Ok(())
+}
+
+pub(super) fn read_stats(
+    collection: &Collection,
+    disk_usage: BTreeMap<String, u64>,
+) -> Result<IndexStats, Error> {
+    let counter = |key: &[u8]| -> Result<u64, Error> {
+        Ok(collection
+            .stats_db()
+            .get(key)?
+            .map(|v| decode(&v))
+            .unwrap_or(0))
+    };
+
+    Ok(IndexStats {
+        collection: collection.name.clone(),
+        pages: counter(PAGES_KEY)?,
+        terms: counter(TERMS_KEY)?,
+        postings: counter(POSTINGS_KEY)?,
+        disk_usage,
+        pages_per_ingester: read_prefixed(collection, INGESTER_PREFIX)?,
+        pages_per_host: read_prefixed(collection, HOST_PREFIX)?,
+    })
+}
+
+fn read_prefixed(collection: &Collection, prefix: &[u8]) -> Result<BTreeMap<String, u64>, Error> {
+    let mut result = BTreeMap::new();
+    for row in collection.stats_db().scan_prefix(prefix) {
+        let (key, value) = row?;
+        let name = String::from_utf8_lossy(&key[prefix.len()..]).to_string();
+        result.insert(name, decode(&value));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::Page;
+    use crate::index_sled::stats::invalidate;
+    use crate::index_sled::Index;
+
+    fn page(i: usize, content: &str) -> Page {
+        Page {
+            url: url::Url::parse(&format!("https://{}.example/{i}", i % 3)).unwrap(),
+            title: format!("page {i}"),
+            content: content.to_string(),
+            ingester: ["even", "odd"][i % 2].to_string(),
+            links: Vec::new(),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_counters_match_recount() {
+        let index = Index::temporary().unwrap();
+        index.stats().unwrap();
+
+        // several workers adding pages that share words at once
+        let mut tasks = Vec::new();
+        for i in 0..20 {
+            let index = index.clone();
+            tasks.push(tokio::spawn(async move {
+                let content = format!("shared words word{i} pair{}", i / 2);
+                index
+                    .add_page(&page(i, &content), time::Duration::ZERO, usize::MAX)
+                    .await
+                    .unwrap();
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        index
+            .add_page(
+                &page(0, "different words now"),
+                time::Duration::ZERO,
+                usize::MAX,
+            )
+            .await
+            .unwrap();
+        assert!(index.delete_page(page(1, "").url.as_str()).unwrap());
+        assert!(index.delete_by_host("2.example").unwrap() > 0);
+        assert!(index.delete_by_ingester("odd", None).unwrap() > 0);
+
+        let kept = index.stats().unwrap();
+        invalidate(&index.collection).unwrap();
+        let recounted = index.stats().unwrap();
+        assert_eq!(kept.pages, recounted.pages);
+        assert_eq!(kept.terms, recounted.terms);
+        assert_eq!(kept.postings, recounted.postings);
+        assert_eq!(kept.pages_per_ingester, recounted.pages_per_ingester);
+        assert_eq!(kept.pages_per_host, recounted.pages_per_host);
+        assert!(kept.pages > 0);
+    }
+}
@@ -0,0 +1,216 @@
+//! Finds the best scoring pages for a query without adding up the score of every page that has one of the words.
+//!
+//! This is the MaxScore algorithm. Each word has an upper bound on the count it can add to a page's score. Once we
+//! have k results, any word whose bound (added to the bounds of every word with a smaller bound) can't beat the
+//! worst of them is no longer used to find new pages. Those words are only looked up for pages found through the
+//! other words, and skipped entirely when the page can't make it into the results even with them.
+//!
+//! For common words this means most of their postings are never decoded, and memory use is limited to one block
+//! per word plus the k results.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::error::Error;
+
+use super::postings::Posting;
+
+/// Something that walks through the postings for a word in page id order.
+pub(super) trait Cursor {
+    /// The posting the cursor is on, None once it has run out.
+    fn current(&self) -> Option<Posting>;
+
+    /// Move on to the next posting.
+    fn advance(&mut self) -> Result<(), Error>;
+
+    /// Move forward to the first posting with a page id of at least id. Does nothing if the cursor is already there.
+    fn seek(&mut self, id: u64) -> Result<(), Error>;
+
+    /// The highest count any posting for this word can have.
+    fn upper_bound(&self) -> u64;
+}
+
+/// The k highest scoring pages, as (page id, score) with the best first. A page's score is the sum of its counts for
+/// every cursor.
+pub(super) fn top_k<C: Cursor>(mut cursors: Vec<C>, k: usize) -> Result<Vec<(u64, u64)>, Error> {
+    if k == 0 {
+        return Ok(Vec::new());
+    }
+
+    cursors.sort_by_key(|c| c.upper_bound());
+    // bounds[i] is the most the first i + 1 cursors can add to a score between them
+    let bounds: Vec<u64> = cursors
+        .iter()
+        .scan(0_u64, |total, c| {
+            *total = total.saturating_add(c.upper_bound());
+            Some(*total)
+        })
+        .collect();
+
+    // min heap of the best results so far. Ties go to the lower page id.
+    let mut results: BinaryHeap<Reverse<(u64, Reverse<u64>)>> = BinaryHeap::with_capacity(k + 1);
+    let mut threshold = 0;
+    // cursors before this are non essential, they can't produce a result on their own
+    let mut first_essential = 0;
+
+    // the next page to score is the lowest one any essential cursor is on
+    while let Some(id) = cursors[first_essential..]
+        .iter()
+        .filter_map(|c| c.current().map(|p| p.0))
+        .min()
+    {
+        let mut score: u64 = 0;
+        for cursor in cursors[first_essential..].iter_mut() {
+            if let Some((posting_id, count)) = cursor.current() {
+                if posting_id == id {
+                    score = score.saturating_add(count);
+                    cursor.advance()?;
+                }
+            }
+        }
+
+        let full = results.len() == k;
+        for i in (0..first_essential).rev() {
+            if full && score.saturating_add(bounds[i]) <= threshold {
+                break;
+            }
+            let cursor = &mut cursors[i];
+            cursor.seek(id)?;
+            if let Some((posting_id, count)) = cursor.current() {
+                if posting_id == id {
+                    score = score.saturating_add(count);
+                }
+            }
+        }
+
+        if !full || score > threshold {
+            results.push(Reverse((score, Reverse(id))));
+            if results.len() > k {
+                results.pop();
+            }
+            if results.len() == k {
+                threshold = results.peek().map(|r| r.0 .0).unwrap_or(0);
+                while first_essential < cursors.len() && bounds[first_essential] <= threshold {
+                    first_essential += 1;
+                }
+            }
+        }
+    }
+
+    let mut results: Vec<(u64, u64)> = results
+        .into_iter()
+        .map(|Reverse((score, Reverse(id)))| (id, score))
+        .collect();
+    results.sort_by_key(|(id, score)| (Reverse(*score), *id));
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::error::Error;
+    use crate::index_sled::postings::Posting;
+    use crate::index_sled::top_k::top_k;
+    use crate::index_sled::top_k::Cursor;
+
+    struct VecCursor {
+        postings: Vec<Posting>,
+        position: usize,
+    }
+
+    impl Cursor for VecCursor {
+        fn current(&self) -> Option<Posting> {
+            self.postings.get(self.position).copied()
+        }
+
+        fn advance(&mut self) -> Result<(), Error> {
+            self.position += 1;
+            Ok(())
+        }
+
+        fn seek(&mut self, id: u64) -> Result<(), Error> {
+            while self.current().is_some_and(|p| p.0 < id) {
+                self.position += 1;
+            }
+            Ok(())
+        }
+
+        fn upper_bound(&self) -> u64 {
+            self.postings.iter().map(|p| p.1).max().unwrap_or(0)
+        }
+    }
+
+    /// Simple linear congruential generator so the test data is the same every run
+    fn numbers(seed: u64) -> impl Iterator<Item = u64> {
+        let mut state = seed;
+        std::iter::repeat_with(move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            state >> 33
+        })
+    }
+
+    #[test]
+    fn test_matches_exhaustive_scoring() {
+        for seed in 0..20 {
+            let mut random = numbers(seed);
+            let words: Vec<Vec<Posting>> = (0..4)
+                .map(|w| {
+                    // a mix of common and rare words
+                    let step = 1 + w * 7;
+                    let mut postings = Vec::new();
+                    for id in 0..2000 {
+                        // a word turns up on about one page in step
+                        let roll = random.next().unwrap() % step;
+                        if roll == 0 {
+                            postings.push((id, 1 + random.next().unwrap() % (5 + w * 3)));
+                        }
+                    }
+                    postings
+                })
+                .collect();
+
+            let mut exhaustive: HashMap<u64, u64> = HashMap::new();
+            for postings in words.iter() {
+                for (id, count) in postings.iter() {
+                    *exhaustive.entry(*id).or_default() += count;
+                }
+            }
+            let mut expected: Vec<u64> = exhaustive.values().copied().collect();
+            expected.sort_unstable_by(|a, b| b.cmp(a));
+            expected.truncate(25);
+
+            let cursors = words
+                .into_iter()
+                .map(|postings| VecCursor {
+                    postings,
+                    position: 0,
+                })
+                .collect();
+            let found = top_k(cursors, 25).unwrap();
+
+            let scores: Vec<u64> = found.iter().map(|r| r.1).collect();
+            assert_eq!(scores, expected, "seed {seed}");
+            for (id, score) in found.iter() {
+                assert_eq!(exhaustive[id], *score, "seed {seed} page {id}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_fewer_pages_than_k() {
+        let cursors = vec![
+            VecCursor {
+                postings: vec![(1, 2), (5, 1)],
+                position: 0,
+            },
+            VecCursor {
+                postings: vec![(5, 4)],
+                position: 0,
+            },
+        ];
+        assert_eq!(top_k(cursors, 10).unwrap(), vec![(5, 5), (1, 2)]);
+    }
+}
@@ -0,0 +1,63 @@
+//! The `ETag` and `Last-Modified` headers the crawler saw for each url, kept with the collection the pages went into.
+//! Keeping them with the pages means they are forgotten when the pages are deleted, so the next crawl fetches the
+//! pages in full and indexes them again rather than being told nothing has changed.
+//!
+//! Values are the name of the ingester that saved them, so everything an ingester fetched (its feeds and sitemaps as
+//! well as its pages) can be forgotten along with its pages, followed by the validators themselves. Only the crawler
+//! looks inside those.
+
+use crate::error::Error;
+use crate::utils::varint;
+
+use super::collection::Collection;
+
+fn split(value: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let mut input = value;
+    let ingester = varint::read_bytes(&mut input).ok_or(Error::BadIndexRecord)?;
+    Ok((ingester, input))
+}
+
+pub(super) fn load(collection: &Collection, url: &str) -> Result<Option<Vec<u8>>, Error> {
+    match collection.validators_db().get(url.as_bytes())? {
+        Some(value) => Ok(Some(split(&value)?.1.to_vec())),
+        None => Ok(None),
+    }
+}
+
+pub(super) fn save(
+    collection: &Collection,
+    url: &str,
+    ingester: &str,
+    validators: &[u8],
+) -> Result<(), Error> {
+    let mut value = Vec::new();
+    varint::write_bytes(&mut value, ingester.as_bytes());
+    value.extend_from_slice(validators);
+    collection.validators_db().insert(url.as_bytes(), value)?;
+    Ok(())
+}
+
+pub(super) fn remove(collection: &Collection, url: &str) -> Result<(), Error> {
+    collection.validators_db().remove(url.as_bytes())?;
+    Ok(())
+}
+
+/// Remove the validators for every url that matches, given the url and the name of the ingester that saved them.
+pub(super) fn remove_matching(
+    collection: &Collection,
+    matches: impl Fn(&str, &str) -> bool,
+) -> Result<(), Error> {
+    let mut batch = sled::Batch::default();
+    for row in collection.validators_db().iter() {
+        let (key, value) = row?;
+        let (ingester, _) = split(&value)?;
+        if matches(
+            &String::from_utf8_lossy(&key),
+            &String::from_utf8_lossy(ingester),
+        ) {
+            batch.remove(key);
+        }
+    }
+    collection.validators_db().apply_batch(batch)?;
+    Ok(())
+}
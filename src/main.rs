@@ -1,11 +1,15 @@
+pub mod cli;
 pub mod config;
 pub mod crawler;
 pub mod data;
 pub mod error;
+pub mod history;
 pub mod index_sled;
+pub mod preferences;
 pub mod server;
 pub mod utils;
 
+use std::env;
 use std::fs;
 use std::fs::OpenOptions;
 use std::io::ErrorKind;
@@ -48,10 +52,14 @@ async fn main() {
         Ok(f) => f,
     };
 
-    // Run the application and handle error responses
-    let result = ceridwen_main().await;
-    if result.is_err() {
-        let err = result.unwrap_err();
+    // Run the application, or a single command if one was given, and handle error responses
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = if args.is_empty() {
+        ceridwen_main().await
+    } else {
+        command_main(&args).await
+    };
+    if let Err(err) = result {
         println!("Error running ceridwen: {}", err);
     }
 
@@ -87,6 +95,13 @@ async fn ceridwen_main() -> Result<(), Error> {
     Ok(())
 }
 
+async fn command_main(args: &[String]) -> Result<(), Error> {
+    let config = Config::load()?;
+    configure_logging(&config)?;
+
+    cli::run_command(&config, args).await
+}
+
 fn configure_logging(config: &Config) -> Result<Handle, Error> {
     let log_path = utils::system_root().join("logs");
 
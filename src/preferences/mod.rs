@@ -0,0 +1,208 @@
+//! Corrections to search results that we know better than the index about. Domains can be boosted up the results or
+//! blocked from them entirely, and urls can be pinned to the top of the results for a query.
+//!
+//! These are kept in `preferences.toml` next to the config, so they can be edited by hand as well as from the command
+//! line and the admin api.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::utils;
+use crate::utils::text_tools::normalise_query;
+
+/// Boost used when one isn't given
+pub const DEFAULT_BOOST: f64 = 2.0;
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct Preferences {
+    /// domain to the number scores of pages on it are multiplied by. Less than 1 pushes pages down the results.
+    #[serde(default)]
+    pub boosted: BTreeMap<String, f64>,
+
+    /// domains that should never show up in results
+    #[serde(default)]
+    pub blocked: BTreeSet<String>,
+
+    /// normalised query to the urls that should be at the top of its results, in order
+    #[serde(default)]
+    pub pinned: BTreeMap<String, Vec<String>>,
+}
+
+impl Preferences {
+    pub fn preferences_path() -> PathBuf {
+        utils::system_root().join("preferences.toml")
+    }
+
+    /// Load the preferences from the standard place on disk. Having no preferences file is the same as having no
+    /// preferences.
+    pub fn load() -> Result<Preferences, Error> {
+        let path = Preferences::preferences_path();
+        if !path.exists() {
+            return Ok(Preferences::default());
+        }
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        fs::write(
+            Preferences::preferences_path(),
+            toml::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+
+    pub fn boost(&mut self, domain: &str, factor: f64) -> Result<(), Error> {
+        if !factor.is_finite() || factor <= 0.0 {
+            return Err(Error::InvalidPreference(format!(
+                "boost for {domain} must be more than 0, got {factor}"
+            )));
+        }
+        self.boosted.insert(normalise_domain(domain), factor);
+        Ok(())
+    }
+
+    /// Returns false if the domain wasn't boosted
+    pub fn remove_boost(&mut self, domain: &str) -> bool {
+        self.boosted.remove(&normalise_domain(domain)).is_some()
+    }
+
+    pub fn block(&mut self, domain: &str) {
+        self.blocked.insert(normalise_domain(domain));
+    }
+
+    /// Returns false if the domain wasn't blocked
+    pub fn unblock(&mut self, domain: &str) -> bool {
+        self.blocked.remove(&normalise_domain(domain))
+    }
+
+    pub fn pin(&mut self, query: &str, url: &str) -> Result<(), Error> {
+        let url = url::Url::parse(url)?.to_string();
+        let query = normalise_query(query);
+        if query.is_empty() {
+            return Err(Error::InvalidPreference(
+                "can not pin a url to an empty query".to_string(),
+            ));
+        }
+        let urls = self.pinned.entry(query).or_default();
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+        Ok(())
+    }
+
+    /// Returns false if the url wasn't pinned for the query
+    pub fn unpin(&mut self, query: &str, url: &str) -> bool {
+        let query = normalise_query(query);
+        // pins are stored as parsed urls so compare against the same form
+        let url = url::Url::parse(url)
+            .map(|u| u.to_string())
+            .unwrap_or_else(|_| url.to_string());
+        let removed = match self.pinned.get_mut(&query) {
+            Some(urls) => {
+                let before = urls.len();
+                urls.retain(|u| *u != url);
+                urls.len() != before
+            }
+            None => false,
+        };
+        if self.pinned.get(&query).is_some_and(|urls| urls.is_empty()) {
+            self.pinned.remove(&query);
+        }
+        removed
+    }
+
+    /// Urls pinned to the top of the results for a query
+    pub fn pins(&self, query: &str) -> &[String] {
+        self.pinned
+            .get(&normalise_query(query))
+            .map(|urls| urls.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// True if any of the domain preferences apply to search results
+    pub fn has_domain_rules(&self) -> bool {
+        !self.boosted.is_empty() || !self.blocked.is_empty()
+    }
+
+    /// True if pages at this url should never be shown
+    pub fn is_blocked(&self, url: &str) -> bool {
+        host(url).is_some_and(|host| domains(&host).any(|d| self.blocked.contains(d)))
+    }
+
+    /// What to multiply the score of the page at url by. Subdomains get the boost of their parent domain unless they
+    /// have their own.
+    pub fn boost_for(&self, url: &str) -> f64 {
+        host(url)
+            .and_then(|host| domains(&host).find_map(|d| self.boosted.get(d).copied()))
+            .unwrap_or(1.0)
+    }
+}
+
+fn host(url: &str) -> Option<String> {
+    url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_lowercase()))
+}
+
+/// Accept a domain given as a url or with a leading www. as well as the plain domain
+fn normalise_domain(domain: &str) -> String {
+    let domain = domain.trim().to_lowercase();
+    let domain = host(&domain).unwrap_or(domain);
+    domain.strip_prefix("www.").unwrap_or(&domain).to_string()
+}
+
+/// The host and every domain it is a part of, most specific first. `a.docs.rs` gives `a.docs.rs`, `docs.rs`, `rs`
+fn domains(host: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(host), |h| h.split_once('.').map(|(_, rest)| rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::preferences::Preferences;
+
+    #[test]
+    fn test_domain_rules() {
+        let mut preferences = Preferences::default();
+        preferences.boost("docs.rs", 3.0).unwrap();
+        preferences
+            .boost("https://www.example.com/some/page", 0.5)
+            .unwrap();
+        preferences.block("Pinterest.com");
+
+        assert_eq!(preferences.boost_for("https://docs.rs/sled"), 3.0);
+        assert_eq!(preferences.boost_for("https://a.docs.rs/sled"), 3.0);
+        assert_eq!(preferences.boost_for("https://notdocs.rs/"), 1.0);
+        assert_eq!(preferences.boost_for("http://example.com/"), 0.5);
+
+        assert!(preferences.is_blocked("https://www.pinterest.com/pin/1"));
+        assert!(preferences.is_blocked("https://pinterest.com/"));
+        assert!(!preferences.is_blocked("https://docs.rs/"));
+
+        assert!(preferences.boost("docs.rs", 0.0).is_err());
+        assert!(preferences.unblock("pinterest.com"));
+        assert!(!preferences.is_blocked("https://pinterest.com/"));
+    }
+
+    #[test]
+    fn test_pins() {
+        let mut preferences = Preferences::default();
+        preferences
+            .pin("Sled Docs", "https://docs.rs/sled")
+            .unwrap();
+        preferences
+            .pin("docs sled", "https://docs.rs/sled")
+            .unwrap();
+
+        assert_eq!(preferences.pins("sled docs"), ["https://docs.rs/sled"]);
+        assert!(preferences.pins("sled").is_empty());
+
+        assert!(preferences.unpin("docs, sled", "https://docs.rs/sled"));
+        assert!(preferences.pinned.is_empty());
+    }
+}
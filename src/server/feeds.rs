@@ -0,0 +1,129 @@
+//! Turns the results of the searches set up in the `feeds` section of the config into atom feeds.
+//!
+//! A feed holds the pages matching its search that were most recently added to the index, newest first. Pages are
+//! ordered by when they were first indexed, so one that is indexed again doesn't jump back to the top. Feed readers
+//! spot new entries by their id, which is the url of the page, so a page only shows up as new once.
+
+use atom_syndication::Entry;
+use atom_syndication::FixedDateTime;
+use atom_syndication::Link;
+use atom_syndication::Text;
+use time::format_description::well_known::Rfc3339;
+
+use crate::config::SearchFeed;
+use crate::data::SearchHit;
+use crate::error::Error;
+
+/// Most entries a feed will have
+pub const FEED_ENTRIES: usize = 50;
+
+fn atom_time(time: time::OffsetDateTime) -> Result<FixedDateTime, Error> {
+    FixedDateTime::parse_from_rfc3339(&time.format(&Rfc3339)?)
+        .map_err(|e| Error::InvalidFeed(e.to_string()))
+}
+
+/// Build the atom feed for a search from the newest pages that match it. self_url is where the feed is served from.
+pub fn build_feed(
+    feed: &SearchFeed,
+    results: &[SearchHit],
+    self_url: &str,
+) -> Result<String, Error> {
+    let mut results: Vec<&SearchHit> = results.iter().collect();
+    results.sort_by_key(|hit| std::cmp::Reverse((hit.page.first_indexed(), hit.id)));
+    results.truncate(FEED_ENTRIES);
+
+    let mut entries = Vec::with_capacity(results.len());
+    for hit in results.iter() {
+        entries.push(Entry {
+            title: Text::plain(hit.page.title.clone()),
+            id: hit.page.url.clone(),
+            updated: atom_time(hit.page.last_index)?,
+            published: Some(atom_time(hit.page.first_indexed())?),
+            links: vec![Link {
+                href: hit.page.url.clone(),
+                ..Default::default()
+            }],
+            summary: Some(Text::plain(hit.page.description.clone())),
+            ..Default::default()
+        });
+    }
+
+    // the feed changed when its most recently updated entry did. Without entries it has never changed.
+    let updated = results
+        .iter()
+        .map(|hit| hit.page.last_index)
+        .max()
+        .unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+
+    let atom = atom_syndication::Feed {
+        title: Text::plain(format!("Ceridwen: {}", feed.name)),
+        id: self_url.to_string(),
+        updated: atom_time(updated)?,
+        subtitle: Some(Text::plain(format!("New pages matching {}", feed.query))),
+        links: vec![Link {
+            href: self_url.to_string(),
+            rel: "self".to_string(),
+            ..Default::default()
+        }],
+        entries,
+        ..Default::default()
+    };
+    Ok(atom.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use atom_syndication::Feed;
+
+    use crate::config::SearchFeed;
+    use crate::data::SearchHit;
+    use crate::server::feeds::build_feed;
+
+    fn hit(url: &str, days_ago: i64) -> SearchHit {
+        SearchHit::example(
+            url,
+            time::macros::datetime!(2026-01-10 12:00 UTC) - time::Duration::days(days_ago),
+        )
+    }
+
+    #[test]
+    fn test_newest_first() {
+        let feed = SearchFeed {
+            name: "otters".to_string(),
+            query: "otter".to_string(),
+            collection: None,
+        };
+        let results = [
+            hit("http://a/", 3),
+            hit("http://b/", 1),
+            hit("http://c/", 2),
+        ];
+        let xml = build_feed(&feed, &results, "http://localhost:8080/feeds/otters.xml").unwrap();
+
+        let parsed: Feed = xml.parse().unwrap();
+        let ids: Vec<&str> = parsed.entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, ["http://b/", "http://c/", "http://a/"]);
+        assert_eq!(parsed.updated.to_rfc3339(), "2026-01-09T12:00:00+00:00");
+        assert_eq!(parsed.links[0].rel, "self");
+    }
+
+    #[test]
+    fn test_indexed_again_keeps_its_place() {
+        let feed = SearchFeed {
+            name: "otters".to_string(),
+            query: "otter".to_string(),
+            collection: None,
+        };
+        // a was added long ago but has just been indexed again
+        let mut again = hit("http://a/", 0);
+        again.page.first_index = Some(time::macros::datetime!(2025-06-01 12:00 UTC));
+        let results = [again, hit("http://b/", 1)];
+        let xml = build_feed(&feed, &results, "http://localhost:8080/feeds/otters.xml").unwrap();
+
+        let parsed: Feed = xml.parse().unwrap();
+        let ids: Vec<&str> = parsed.entries.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, ["http://b/", "http://a/"]);
+        // but it was still the last entry to change
+        assert_eq!(parsed.updated.to_rfc3339(), "2026-01-10T12:00:00+00:00");
+    }
+}
@@ -1,42 +1,80 @@
+use std::collections::BTreeMap;
 use std::collections::HashSet;
 use std::env;
 use std::io;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
 
 use crate::config::Config;
-use crate::data::SearchResult;
+use crate::config::Ingester;
+use crate::data::SearchHit;
+use crate::history::History;
+use crate::history::HistoryEntry;
+use crate::history::SavedSearch;
+use crate::index_sled::newest_all;
+use crate::index_sled::search_all;
 use crate::index_sled::Index;
+use crate::preferences::Preferences;
+use crate::preferences::DEFAULT_BOOST;
 use actix_files::NamedFile;
+use actix_web::delete;
 use actix_web::dev::Server;
 use actix_web::get;
 use actix_web::http::header::ContentType;
+use actix_web::http::header::LOCATION;
 use actix_web::middleware::Logger;
 use actix_web::post;
+use actix_web::put;
 use actix_web::web;
 use actix_web::App;
 use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use actix_web::HttpServer;
+use humansize::format_size;
+use humansize::DECIMAL;
 use log::debug;
 use log::info;
 use log::warn;
 use serde::Deserialize;
+use serde::Serialize;
 use tera::Context;
 use tera::Tera;
 
 use crate::error::Error;
 
+mod feeds;
+mod search_cache;
+
+use search_cache::SearchCache;
+
 pub struct AppData {
-    _config: Config,
+    config: Config,
     templates: Tera,
+    search_cache: SearchCache,
+    preferences: RwLock<Preferences>,
+    history: History,
+    search_history: bool,
 }
 
 pub fn run_server(config: Config) -> Result<Server, Error> {
     info!("Server starting.");
 
+    let history = if config.server.search_history {
+        History::open(config.server.search_history_size)?
+    } else {
+        let history = History::open(0)?;
+        history.clear()?;
+        history
+    };
+
     let app_data = AppData {
-        _config: config.clone(),
+        config: config.clone(),
         templates: load_templates()?,
+        search_cache: SearchCache::new(config.server.search_cache_size),
+        preferences: RwLock::new(Preferences::load()?),
+        history,
+        search_history: config.server.search_history,
     };
 
     let web_data = web::Data::new(app_data);
@@ -48,6 +86,28 @@ pub fn run_server(config: Config) -> Result<Server, Error> {
             .app_data(web_data.clone())
             .service(post_search)
             .service(get_search)
+            .service(click)
+            .service(history_page)
+            .service(api_history)
+            .service(clear_history)
+            .service(save_search)
+            .service(saved_search_page)
+            .service(api_saved_search)
+            .service(delete_saved_search)
+            .service(search_feed)
+            // Admin api
+            .service(admin_delete_page)
+            .service(admin_delete_host)
+            .service(admin_delete_ingester)
+            .service(api_preferences)
+            .service(admin_boost_domain)
+            .service(admin_unboost_domain)
+            .service(admin_block_domain)
+            .service(admin_unblock_domain)
+            .service(admin_pin)
+            .service(admin_unpin)
+            .service(api_stats)
+            .service(stats_page)
             // General file routes. images, css, and javascript
             .route(
                 "/img/{filename:.*\\.(jpg|png|webp)}",
@@ -137,13 +197,19 @@ fn get_root_path(path: &str) -> PathBuf {
 #[derive(Deserialize)]
 struct SearchParams {
     q: String,
+    /// only search this collection. Searches every collection when not given.
+    collection: Option<String>,
 }
 
 #[post("/search")]
-async fn post_search(info: web::Query<SearchParams>) -> Result<HttpResponse, Error> {
+async fn post_search(
+    app_data: web::Data<AppData>,
+    info: web::Query<SearchParams>,
+) -> Result<HttpResponse, Error> {
     info!("post search!!! {}", info.q);
-    let results = get_search_results(&info.q).await?;
-    Ok(HttpResponse::Ok().json(results))
+    let results = get_search_results(&app_data, &info.q, info.collection.as_deref()).await?;
+    record_search(&app_data, &info, results.len());
+    Ok(HttpResponse::Ok().json(results.as_ref()))
 }
 
 #[get("/search")]
@@ -152,12 +218,13 @@ async fn get_search(
     info: web::Query<SearchParams>,
 ) -> Result<HttpResponse, Error> {
     info!("get search!!! {}", info.q);
-    let results = get_search_results(&info.q).await?;
+    let results = get_search_results(&app_data, &info.q, info.collection.as_deref()).await?;
+    record_search(&app_data, &info, results.len());
 
     // now to render the search results page
     let mut context = Context::new();
-    context.insert("search_results", &results);
-    context.insert("search_term", &info.q);
+    context.insert("search_results", results.as_ref());
+    insert_search_term(&mut context, &info.q, &info.collection);
 
     let page_text = app_data.templates.render("search.html", &context)?;
 
@@ -166,13 +233,486 @@ async fn get_search(
         .body(page_text))
 }
 
-async fn get_search_results(q: &str) -> Result<Vec<SearchResult>, Error> {
-    let index = Index::load().await?;
+fn insert_search_term(context: &mut Context, q: &str, collection: &Option<String>) {
+    context.insert("search_term", q);
+    // tera can't url encode things for us without extra features, so do it here for the click links
+    context.insert("search_term_encoded", &encode(q));
+    context.insert("collection", collection);
+}
+
+/// Add a search to the history. Failing to do so shouldn't stop the results being shown, so errors are only logged.
+fn record_search(app_data: &AppData, info: &SearchParams, results: usize) {
+    if let Err(e) = app_data
+        .history
+        .record(&info.q, info.collection.as_deref(), results)
+    {
+        warn!("Could not record search for {} in the history: {e}", info.q);
+    }
+}
+
+async fn get_search_results(
+    app_data: &AppData,
+    q: &str,
+    collection: Option<&str>,
+) -> Result<Arc<Vec<SearchHit>>, Error> {
+    let indexes = match collection {
+        Some(name) => vec![Index::open_existing(name).await?],
+        None => Index::open_all().await?,
+    };
+
+    // take the generations before searching, so anything written while we search makes the results stale
+    let generations = indexes
+        .iter()
+        .map(|i| (i.collection_name().to_string(), i.generation()))
+        .collect();
+    if let Some(results) = app_data.search_cache.get(q, collection, &generations) {
+        debug!("Using cached results for {q}");
+        return Ok(results);
+    }
 
-    let results = index.search(q).await?;
+    // cloned so the lock isn't held across the search
+    let preferences = app_data
+        .preferences
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let results = Arc::new(search_all(&indexes, q, &preferences).await?);
+    app_data
+        .search_cache
+        .insert(q, collection, generations, results.clone());
     Ok(results)
 }
 
+/// Number of past searches shown in the history
+const HISTORY_PAGE_SIZE: usize = 100;
+
+#[derive(Serialize)]
+struct HistoryResponse {
+    searches: Vec<HistoryEntry>,
+    saved: Vec<SavedSearch>,
+}
+
+#[get("/api/history")]
+async fn api_history(app_data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(HistoryResponse {
+        searches: app_data.history.recent(HISTORY_PAGE_SIZE)?,
+        saved: app_data.history.saved_searches()?,
+    }))
+}
+
+/// Something on the history page, along with the url to run it again.
+#[derive(Serialize)]
+struct HistoryLink<T: Serialize> {
+    #[serde(flatten)]
+    item: T,
+    link: String,
+}
+
+fn encode(s: &str) -> String {
+    url::form_urlencoded::byte_serialize(s.as_bytes()).collect()
+}
+
+#[get("/history")]
+async fn history_page(app_data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    let searches: Vec<HistoryLink<HistoryEntry>> = app_data
+        .history
+        .recent(HISTORY_PAGE_SIZE)?
+        .into_iter()
+        .map(|entry| {
+            let mut link = format!("/search?q={}", encode(&entry.query));
+            if let Some(collection) = entry.collection.as_ref() {
+                link.push_str(&format!("&collection={}", encode(collection)));
+            }
+            HistoryLink { item: entry, link }
+        })
+        .collect();
+    let saved: Vec<HistoryLink<SavedSearch>> = app_data
+        .history
+        .saved_searches()?
+        .into_iter()
+        .map(|search| {
+            let link = format!("/saved/{}", encode(&search.name).replace('+', "%20"));
+            HistoryLink { item: search, link }
+        })
+        .collect();
+
+    let mut context = Context::new();
+    context.insert("searches", &searches);
+    context.insert("saved_searches", &saved);
+    context.insert("history_enabled", &app_data.search_history);
+
+    let page_text = app_data.templates.render("history.html", &context)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page_text))
+}
+
+fn redirect_to_history() -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, "/history"))
+        .finish()
+}
+
+#[post("/history/clear")]
+async fn clear_history(app_data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    info!("clearing search history");
+    app_data.history.clear()?;
+    Ok(redirect_to_history())
+}
+
+#[derive(Deserialize)]
+struct SaveSearchParams {
+    name: String,
+    q: String,
+    collection: Option<String>,
+}
+
+#[post("/saved")]
+async fn save_search(
+    app_data: web::Data<AppData>,
+    form: web::Form<SaveSearchParams>,
+) -> Result<HttpResponse, Error> {
+    info!("saving search {} as {}", form.q, form.name);
+    // forms send an empty string rather than leaving the field out
+    let collection = form.collection.as_deref().filter(|c| !c.is_empty());
+    app_data
+        .history
+        .save_search(&form.name, &form.q, collection)?;
+    Ok(redirect_to_history())
+}
+
+#[post("/saved/{name}/delete")]
+async fn delete_saved_search(
+    app_data: web::Data<AppData>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    info!("deleting saved search {name}");
+    app_data.history.delete_saved(&name)?;
+    Ok(redirect_to_history())
+}
+
+/// A result of a saved search, and whether the search has shown it before.
+#[derive(Serialize)]
+struct SavedSearchHit<'a> {
+    #[serde(flatten)]
+    hit: &'a SearchHit,
+    new: bool,
+}
+
+/// Run a saved search, returning it as it was before this run so last_run is when it was previously run.
+async fn run_saved_search(
+    app_data: &AppData,
+    name: &str,
+) -> Result<Option<(SavedSearch, Arc<Vec<SearchHit>>, Vec<bool>)>, Error> {
+    let search = match app_data.history.saved_search(name)? {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let results = get_search_results(app_data, &search.query, search.collection.as_deref()).await?;
+    let new = app_data.history.mark_seen(name, &results)?;
+    Ok(Some((search, results, new)))
+}
+
+fn saved_search_hits<'a>(results: &'a [SearchHit], new: &[bool]) -> Vec<SavedSearchHit<'a>> {
+    results
+        .iter()
+        .zip(new.iter())
+        .map(|(hit, new)| SavedSearchHit { hit, new: *new })
+        .collect()
+}
+
+#[get("/api/saved/{name}")]
+async fn api_saved_search(
+    app_data: web::Data<AppData>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    match run_saved_search(&app_data, &name).await? {
+        Some((_, results, new)) => Ok(HttpResponse::Ok().json(saved_search_hits(&results, &new))),
+        None => Ok(HttpResponse::NotFound().body(format!("No saved search called {name}"))),
+    }
+}
+
+#[get("/saved/{name}")]
+async fn saved_search_page(
+    app_data: web::Data<AppData>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let (search, results, new) = match run_saved_search(&app_data, &name).await? {
+        Some(r) => r,
+        None => return Ok(HttpResponse::NotFound().body(format!("No saved search called {name}"))),
+    };
+
+    let mut context = Context::new();
+    context.insert("search_results", &saved_search_hits(&results, &new));
+    context.insert("saved_search", &search);
+    insert_search_term(&mut context, &search.query, &search.collection);
+
+    let page_text = app_data.templates.render("search.html", &context)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page_text))
+}
+
+#[get("/feeds/{name}.xml")]
+async fn search_feed(
+    app_data: web::Data<AppData>,
+    name: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let feed = match app_data.config.feeds.iter().find(|f| f.name == *name) {
+        Some(f) => f,
+        None => return Ok(HttpResponse::NotFound().body(format!("No feed called {name}"))),
+    };
+    let indexes = match feed.collection.as_deref() {
+        Some(name) => vec![Index::open_existing(name).await?],
+        None => Index::open_all().await?,
+    };
+    let preferences = app_data
+        .preferences
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let results = newest_all(&indexes, &feed.query, &preferences, feeds::FEED_ENTRIES).await?;
+
+    let connection = req.connection_info();
+    let self_url = format!(
+        "{}://{}{}",
+        connection.scheme(),
+        connection.host(),
+        req.path()
+    );
+    let xml = feeds::build_feed(feed, &results, &self_url)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(xml))
+}
+
+#[derive(Deserialize)]
+struct ClickParams {
+    id: u64,
+    #[serde(default)]
+    q: String,
+    collection: Option<String>,
+}
+
+/// Search results link here rather than straight to the page, so we can learn which results are chosen for which
+/// searches before sending the browser on to the page.
+#[get("/click")]
+async fn click(info: web::Query<ClickParams>) -> Result<HttpResponse, Error> {
+    let index = open_collection(&info.collection).await?;
+    match index.record_click(info.id, &info.q)? {
+        Some(page) => {
+            debug!("click on {} for {}", page.url, info.q);
+            Ok(HttpResponse::SeeOther()
+                .insert_header((LOCATION, page.url))
+                .finish())
+        }
+        None => Ok(HttpResponse::NotFound().body(format!("No page with id {}", info.id))),
+    }
+}
+
+/// Admin and stats endpoints work on the default collection unless given one of these.
+#[derive(Deserialize)]
+struct CollectionParams {
+    collection: Option<String>,
+}
+
+async fn open_collection(collection: &Option<String>) -> Result<Index, Error> {
+    match collection {
+        Some(name) => Index::open_existing(name).await,
+        None => Index::load().await,
+    }
+}
+
+#[derive(Deserialize)]
+struct DeletePageParams {
+    url: String,
+    collection: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeleteResponse {
+    deleted: usize,
+}
+
+#[delete("/admin/page")]
+async fn admin_delete_page(info: web::Query<DeletePageParams>) -> Result<HttpResponse, Error> {
+    info!("admin delete page {}", info.url);
+    let index = open_collection(&info.collection).await?;
+    let deleted = if index.delete_page(&info.url)? { 1 } else { 0 };
+    Ok(HttpResponse::Ok().json(DeleteResponse { deleted }))
+}
+
+#[delete("/admin/host/{host}")]
+async fn admin_delete_host(
+    host: web::Path<String>,
+    params: web::Query<CollectionParams>,
+) -> Result<HttpResponse, Error> {
+    info!("admin delete host {}", host);
+    let index = open_collection(&params.collection).await?;
+    let deleted = index.delete_by_host(&host)?;
+    Ok(HttpResponse::Ok().json(DeleteResponse { deleted }))
+}
+
+#[delete("/admin/ingester/{name}")]
+async fn admin_delete_ingester(
+    name: web::Path<String>,
+    params: web::Query<CollectionParams>,
+    app_data: web::Data<AppData>,
+) -> Result<HttpResponse, Error> {
+    info!("admin delete ingester {}", name);
+    let index = open_collection(&params.collection).await?;
+    let legacy_host = app_data.config.ingester(&name).and_then(Ingester::host);
+    let deleted = index.delete_by_ingester(&name, legacy_host.as_deref())?;
+    Ok(HttpResponse::Ok().json(DeleteResponse { deleted }))
+}
+
+#[get("/api/preferences")]
+async fn api_preferences(app_data: web::Data<AppData>) -> Result<HttpResponse, Error> {
+    let preferences = app_data
+        .preferences
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    Ok(HttpResponse::Ok().json(preferences))
+}
+
+/// Make a change to the preferences and save them. Cached results were found with the old preferences so are thrown
+/// away.
+fn update_preferences<T>(
+    app_data: &AppData,
+    change: impl FnOnce(&mut Preferences) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut preferences = app_data
+        .preferences
+        .write()
+        .unwrap_or_else(|e| e.into_inner());
+    let result = change(&mut preferences)?;
+    preferences.save()?;
+    app_data.search_cache.clear();
+    Ok(result)
+}
+
+#[derive(Serialize)]
+struct PreferenceResponse {
+    changed: bool,
+}
+
+#[derive(Deserialize)]
+struct BoostParams {
+    factor: Option<f64>,
+}
+
+#[put("/admin/preferences/boost/{domain}")]
+async fn admin_boost_domain(
+    app_data: web::Data<AppData>,
+    domain: web::Path<String>,
+    params: web::Query<BoostParams>,
+) -> Result<HttpResponse, Error> {
+    let factor = params.factor.unwrap_or(DEFAULT_BOOST);
+    info!("admin boost domain {domain} by {factor}");
+    update_preferences(&app_data, |p| p.boost(&domain, factor))?;
+    Ok(HttpResponse::Ok().json(PreferenceResponse { changed: true }))
+}
+
+#[delete("/admin/preferences/boost/{domain}")]
+async fn admin_unboost_domain(
+    app_data: web::Data<AppData>,
+    domain: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    info!("admin remove boost for domain {domain}");
+    let changed = update_preferences(&app_data, |p| Ok(p.remove_boost(&domain)))?;
+    Ok(HttpResponse::Ok().json(PreferenceResponse { changed }))
+}
+
+#[put("/admin/preferences/block/{domain}")]
+async fn admin_block_domain(
+    app_data: web::Data<AppData>,
+    domain: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    info!("admin block domain {domain}");
+    update_preferences(&app_data, |p| {
+        p.block(&domain);
+        Ok(())
+    })?;
+    Ok(HttpResponse::Ok().json(PreferenceResponse { changed: true }))
+}
+
+#[delete("/admin/preferences/block/{domain}")]
+async fn admin_unblock_domain(
+    app_data: web::Data<AppData>,
+    domain: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    info!("admin unblock domain {domain}");
+    let changed = update_preferences(&app_data, |p| Ok(p.unblock(&domain)))?;
+    Ok(HttpResponse::Ok().json(PreferenceResponse { changed }))
+}
+
+#[derive(Deserialize)]
+struct PinParams {
+    q: String,
+    url: String,
+}
+
+#[put("/admin/preferences/pin")]
+async fn admin_pin(
+    app_data: web::Data<AppData>,
+    params: web::Query<PinParams>,
+) -> Result<HttpResponse, Error> {
+    info!("admin pin {} for {}", params.url, params.q);
+    update_preferences(&app_data, |p| p.pin(&params.q, &params.url))?;
+    Ok(HttpResponse::Ok().json(PreferenceResponse { changed: true }))
+}
+
+#[delete("/admin/preferences/pin")]
+async fn admin_unpin(
+    app_data: web::Data<AppData>,
+    params: web::Query<PinParams>,
+) -> Result<HttpResponse, Error> {
+    info!("admin unpin {} for {}", params.url, params.q);
+    let changed = update_preferences(&app_data, |p| Ok(p.unpin(&params.q, &params.url)))?;
+    Ok(HttpResponse::Ok().json(PreferenceResponse { changed }))
+}
+
+#[get("/api/stats")]
+async fn api_stats(params: web::Query<CollectionParams>) -> Result<HttpResponse, Error> {
+    let index = open_collection(&params.collection).await?;
+    let stats = index.stats()?;
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+#[get("/stats")]
+async fn stats_page(
+    app_data: web::Data<AppData>,
+    params: web::Query<CollectionParams>,
+) -> Result<HttpResponse, Error> {
+    let index = open_collection(&params.collection).await?;
+    let stats = index.stats()?;
+
+    let disk_usage: BTreeMap<&String, String> = stats
+        .disk_usage
+        .iter()
+        .map(|(name, size)| (name, format_size(*size, DECIMAL)))
+        .collect();
+
+    let mut context = Context::new();
+    context.insert("stats", &stats);
+    context.insert("disk_usage", &disk_usage);
+    context.insert(
+        "total_disk_usage",
+        &format_size(stats.total_disk_usage(), DECIMAL),
+    );
+
+    let page_text = app_data.templates.render("stats.html", &context)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page_text))
+}
+
 fn load_templates() -> Result<Tera, Error> {
     let template_dir = env::current_exe()?
         .parent()
@@ -184,7 +724,13 @@ fn load_templates() -> Result<Tera, Error> {
 
     tera.autoescape_on(vec![]);
 
-    let mut required_templates = HashSet::from(["index.html", "search.html", "header.html"]);
+    let mut required_templates = HashSet::from([
+        "index.html",
+        "search.html",
+        "header.html",
+        "stats.html",
+        "history.html",
+    ]);
 
     info!("Loaded templates:");
     for template in tera.get_template_names() {
@@ -0,0 +1,118 @@
+//! Keeps the results of recent searches so paging through or refreshing a search doesn't scan the index again.
+//!
+//! Entries remember the generation of every collection they were built from. Once the crawler writes to one of those
+//! collections its generation changes and the entry is thrown away the next time it is looked up.
+
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use lru::LruCache;
+
+use crate::data::SearchHit;
+use crate::utils::text_tools::normalise_query;
+
+/// Name and generation of each collection a search looked at.
+pub type Generations = Vec<(String, u64)>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    query: String,
+    collection: Option<String>,
+}
+
+struct CacheEntry {
+    generations: Generations,
+    results: Arc<Vec<SearchHit>>,
+}
+
+pub struct SearchCache {
+    /// None when the cache is turned off
+    entries: Option<Mutex<LruCache<CacheKey, CacheEntry>>>,
+}
+
+impl SearchCache {
+    pub fn new(size: usize) -> Self {
+        SearchCache {
+            entries: NonZeroUsize::new(size).map(|s| Mutex::new(LruCache::new(s))),
+        }
+    }
+
+    /// Get the results for a search if we have them and nothing has changed since they were found.
+    pub fn get(
+        &self,
+        query: &str,
+        collection: Option<&str>,
+        generations: &Generations,
+    ) -> Option<Arc<Vec<SearchHit>>> {
+        let mut entries = self
+            .entries
+            .as_ref()?
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let key = CacheKey {
+            query: normalise_query(query),
+            collection: collection.map(str::to_string),
+        };
+
+        match entries.get(&key) {
+            Some(entry) if entry.generations == *generations => Some(entry.results.clone()),
+            Some(_) => {
+                entries.pop(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(
+        &self,
+        query: &str,
+        collection: Option<&str>,
+        generations: Generations,
+        results: Arc<Vec<SearchHit>>,
+    ) {
+        if let Some(entries) = self.entries.as_ref() {
+            let key = CacheKey {
+                query: normalise_query(query),
+                collection: collection.map(str::to_string),
+            };
+            entries.lock().unwrap_or_else(|e| e.into_inner()).put(
+                key,
+                CacheEntry {
+                    generations,
+                    results,
+                },
+            );
+        }
+    }
+
+    /// Throw away everything, for when something other than the index changes the results.
+    pub fn clear(&self) {
+        if let Some(entries) = self.entries.as_ref() {
+            entries.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::server::search_cache::SearchCache;
+
+    #[test]
+    fn test_stale_entries() {
+        let cache = SearchCache::new(10);
+        let generations = vec![("default".to_string(), 3)];
+        cache.insert("otters", None, generations.clone(), Arc::new(Vec::new()));
+
+        assert!(cache.get("Otters", None, &generations).is_some());
+        assert!(cache.get("otters", Some("default"), &generations).is_none());
+
+        // the index has changed since the search was cached
+        let newer = vec![("default".to_string(), 4)];
+        assert!(cache.get("otters", None, &newer).is_none());
+        assert!(cache.get("otters", None, &generations).is_none());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod percentage;
 pub mod text_tools;
+pub mod varint;
 
 use std::env;
 use std::fs;
@@ -19,6 +19,15 @@ pub fn tokenise(text: &str) -> Vec<String> {
         .collect()
 }
 
+/// Reduce a query to the words the index will look for, so queries that only differ in case, punctuation or word
+/// order are treated as the same query.
+pub fn normalise_query(query: &str) -> String {
+    let mut words = tokenise(query);
+    words.retain(|w| !w.is_empty());
+    words.sort();
+    words.join(" ")
+}
+
 pub fn filter(words: Vec<String>) -> Vec<String> {
     words
         .into_iter()
@@ -46,3 +55,14 @@ const STOP_WORDS: &[&str] = &[
     "all", "any", "both", "each", "few", "more", "most", "other", "some", "such", "only", "own",
     "same", "so", "than", "too", "very", "s", "t", "can", "will", "just", "should",
 ];
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::text_tools::normalise_query;
+
+    #[test]
+    fn test_normalise_query() {
+        assert_eq!(normalise_query("Otters eat fish"), "eat fish otters");
+        assert_eq!(normalise_query("  fish, OTTERS eat! "), "eat fish otters");
+    }
+}
@@ -0,0 +1,115 @@
+//! Variable length integer encoding (LEB128 style) for packing numbers into as few bytes as possible.
+//! Small numbers take a single byte, a full u64 takes ten.
+//!
+//! The read functions take a mutable slice and move it past whatever they have read, so a record can be decoded by
+//! calling them one after another. They return None if the input runs out part way through a value.
+
+pub fn write_u64(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+pub fn read_u64(input: &mut &[u8]) -> Option<u64> {
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let (byte, rest) = input.split_first()?;
+        *input = rest;
+        if shift >= 64 {
+            return None;
+        }
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+        shift += 7;
+    }
+}
+
+/// Signed values are zig-zag encoded so small negative numbers stay small.
+pub fn write_i64(buffer: &mut Vec<u8>, value: i64) {
+    write_u64(buffer, ((value << 1) ^ (value >> 63)) as u64)
+}
+
+pub fn read_i64(input: &mut &[u8]) -> Option<i64> {
+    let value = read_u64(input)?;
+    Some(((value >> 1) as i64) ^ -((value & 1) as i64))
+}
+
+/// Write a length prefixed run of bytes
+pub fn write_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    write_u64(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+pub fn read_bytes<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let length = usize::try_from(read_u64(input)?).ok()?;
+    if length > input.len() {
+        return None;
+    }
+    let (bytes, rest) = input.split_at(length);
+    *input = rest;
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::varint::read_bytes;
+    use crate::utils::varint::read_i64;
+    use crate::utils::varint::read_u64;
+    use crate::utils::varint::write_bytes;
+    use crate::utils::varint::write_i64;
+    use crate::utils::varint::write_u64;
+
+    #[test]
+    fn test_round_trip() {
+        let unsigned = [0, 1, 127, 128, 300, 16_384, u32::MAX as u64, u64::MAX];
+        let signed = [0, 1, -1, 63, -64, 1_700_000_000, i64::MIN, i64::MAX];
+
+        let mut buffer = Vec::new();
+        for value in unsigned.iter() {
+            write_u64(&mut buffer, *value);
+        }
+        for value in signed.iter() {
+            write_i64(&mut buffer, *value);
+        }
+        write_bytes(&mut buffer, b"some bytes");
+
+        let mut input = buffer.as_slice();
+        for value in unsigned.iter() {
+            assert_eq!(read_u64(&mut input), Some(*value));
+        }
+        for value in signed.iter() {
+            assert_eq!(read_i64(&mut input), Some(*value));
+        }
+        assert_eq!(read_bytes(&mut input), Some(&b"some bytes"[..]));
+        assert!(input.is_empty(), "Should have read everything");
+    }
+
+    #[test]
+    fn test_small_values_are_small() {
+        let mut buffer = Vec::new();
+        write_u64(&mut buffer, 127);
+        assert_eq!(buffer.len(), 1);
+
+        buffer.clear();
+        write_i64(&mut buffer, -64);
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let mut buffer = Vec::new();
+        write_u64(&mut buffer, 300);
+        let mut input = &buffer[..1];
+        assert_eq!(read_u64(&mut input), None);
+
+        buffer.clear();
+        write_bytes(&mut buffer, b"abc");
+        let mut input = &buffer[..2];
+        assert_eq!(read_bytes(&mut input), None);
+    }
+}